@@ -0,0 +1,170 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use odbc_api::{
+    handles::{AsHandle, Statement},
+    sys::{HStmt, Handle, HandleType, SQLCancel},
+};
+use sqlx_core::error::Error;
+
+use crate::error::check;
+
+// SQLCancel is the one ODBC function which may be called on a statement while another thread is
+// executing it. The worker registers the statement it is executing in a `RunningStatement`, which
+// is shared with everybody who may want to cancel it. SQLCancel is only called while the worker
+// is inside a driver call. On an idle statement it would close the cursor instead, which the
+// worker would not know about.
+
+/// The statement the worker of a connection is executing, if any.
+#[derive(Default)]
+pub(crate) struct RunningStatement {
+    // The lock is held while cancelling, and the worker takes it to unregister the statement
+    // before the handle can be freed.
+    current: Mutex<Option<Running>>,
+    next_execution: AtomicU64,
+}
+
+struct Running {
+    execution: u64,
+    stmt: HStmt,
+    /// Whether the worker is inside SQLExecute, SQLFetch or SQLMoreResults for the statement.
+    in_call: bool,
+}
+
+// Safe: the handle is only used for SQLCancel, and only while it is registered.
+unsafe impl Send for RunningStatement {}
+unsafe impl Sync for RunningStatement {}
+
+impl RunningStatement {
+    /// Returns the id for a new execution, which identifies it in `cancel`.
+    pub(crate) fn next_execution(&self) -> u64 {
+        self.next_execution.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Registers `stmt` as executing until the returned guard is dropped.
+    pub(crate) fn start<'a>(&'a self, execution: u64, stmt: &impl Statement) -> RunningGuard<'a> {
+        *self.lock() = Some(Running {
+            execution,
+            stmt: stmt.as_sys(),
+            in_call: false,
+        });
+        RunningGuard(self)
+    }
+
+    /// Marks the registered statement as being inside a driver call which may be cancelled,
+    /// until the returned guard is dropped.
+    pub(crate) fn driver_call(&self) -> DriverCall<'_> {
+        self.set_in_call(true);
+        DriverCall(self)
+    }
+
+    fn set_in_call(&self, in_call: bool) {
+        if let Some(running) = self.lock().as_mut() {
+            running.in_call = in_call;
+        }
+    }
+
+    /// Cancels the execution with the given id, or whatever is executing if it is `None`. Does
+    /// nothing if that is not inside a driver call.
+    pub(crate) fn cancel(&self, execution: Option<u64>) -> Result<(), Error> {
+        match *self.lock() {
+            Some(Running {
+                execution: current,
+                stmt,
+                in_call: true,
+            }) if execution.is_none() || execution == Some(current) => {
+                let ret = unsafe { SQLCancel(stmt) };
+                check(ret, "SQLCancel", &RawStatement(stmt))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Running>> {
+        // Nothing can panic while the lock is held, so it cannot be poisoned
+        self.current.lock().unwrap()
+    }
+}
+
+/// Unregisters the executing statement when dropped.
+pub(crate) struct RunningGuard<'a>(&'a RunningStatement);
+
+impl Drop for RunningGuard<'_> {
+    fn drop(&mut self) {
+        *self.0.lock() = None;
+    }
+}
+
+/// Ends a driver call when dropped, see [`RunningStatement::driver_call`].
+pub(crate) struct DriverCall<'a>(&'a RunningStatement);
+
+impl Drop for DriverCall<'_> {
+    fn drop(&mut self) {
+        self.0.set_in_call(false);
+    }
+}
+
+/// A statement handle for reading diagnostics, which does not free it on drop.
+struct RawStatement(HStmt);
+
+unsafe impl AsHandle for RawStatement {
+    fn as_handle(&self) -> Handle {
+        self.0 as Handle
+    }
+
+    fn handle_type(&self) -> HandleType {
+        HandleType::Stmt
+    }
+}
+
+/// Cancels the statement an execution is running when dropped, e.g. along with the stream of
+/// its results. If no driver call is in progress, the worker notices the dropped stream instead
+/// and closes the cursor itself.
+pub(crate) struct CancelOnDrop {
+    pub(crate) running: Arc<RunningStatement>,
+    pub(crate) execution: u64,
+}
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        if let Err(e) = self.running.cancel(Some(self.execution)) {
+            log::warn!("failed to cancel statement: {e}");
+        }
+    }
+}
+
+/// Cancels the statement an [`ODBCConnection`](crate::ODBCConnection) is executing, from
+/// another task or thread. Obtained with
+/// [`ODBCConnection::cancel_handle`](crate::ODBCConnection::cancel_handle).
+///
+/// ```no_run
+/// # async fn example(conn: &mut sqlx_odbc::ODBCConnection) -> Result<(), sqlx::Error> {
+/// let cancel = conn.cancel_handle();
+/// std::thread::spawn(move || {
+///     std::thread::sleep(std::time::Duration::from_secs(10));
+///     cancel.cancel()
+/// });
+/// // Fails with SQLSTATE HY008 if it takes longer than 10 seconds
+/// sqlx::query("SELECT * FROM large_table").fetch_all(conn).await?;
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone)]
+pub struct CancelHandle {
+    pub(crate) running: Arc<RunningStatement>,
+}
+
+impl CancelHandle {
+    /// Cancels the statement which is executing right now. Does nothing if the connection is
+    /// idle, or waits for the results to be read. The execution fails with SQLSTATE HY008
+    /// (operation canceled).
+    pub fn cancel(&self) -> Result<(), Error> {
+        self.running.cancel(None)
+    }
+}
+
+impl std::fmt::Debug for CancelHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("CancelHandle").finish()
+    }
+}
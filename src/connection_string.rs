@@ -0,0 +1,111 @@
+use std::borrow::Cow;
+
+use sqlx_core::error::Error;
+
+// ODBC connection strings are a list of `Key=Value;` attributes. Values containing special
+// characters are enclosed in braces, in which a `}` is written as `}}`.
+
+/// Splits a connection string into its attributes.
+pub(crate) fn parse(s: &str) -> Result<Vec<(String, String)>, Error> {
+    let mut attributes = Vec::new();
+    let mut chars = s.chars().peekable();
+    loop {
+        while chars.next_if(|c| *c == ';' || c.is_whitespace()).is_some() {}
+        if chars.peek().is_none() {
+            return Ok(attributes);
+        }
+
+        let mut key = String::new();
+        loop {
+            match chars.next() {
+                Some('=') => break,
+                Some(c) => key.push(c),
+                None => {
+                    return Err(Error::Configuration(
+                        format!("missing `=` after `{}` in connection string", key.trim()).into(),
+                    ))
+                }
+            }
+        }
+
+        while chars.next_if(|c| *c == ' ').is_some() {}
+        let mut value = String::new();
+        if chars.next_if_eq(&'{').is_some() {
+            loop {
+                match chars.next() {
+                    Some('}') if chars.next_if_eq(&'}').is_some() => value.push('}'),
+                    Some('}') => break,
+                    Some(c) => value.push(c),
+                    None => {
+                        return Err(Error::Configuration(
+                            format!("unterminated `{{` in value of `{}`", key.trim()).into(),
+                        ))
+                    }
+                }
+            }
+            while chars.next_if(|c| *c == ' ').is_some() {}
+            if !matches!(chars.next(), None | Some(';')) {
+                return Err(Error::Configuration(
+                    format!("expected `;` after value of `{}`", key.trim()).into(),
+                ));
+            }
+        } else {
+            while let Some(c) = chars.next_if(|c| *c != ';') {
+                value.push(c);
+            }
+            value.truncate(value.trim_end().len());
+        }
+
+        attributes.push((key.trim().to_owned(), value));
+    }
+}
+
+/// Encloses `value` in braces if it could not be part of a connection string otherwise.
+pub(crate) fn escape(value: &str) -> Cow<'_, str> {
+    // `+` is escaped as well, as the PostgreSQL driver fails on passwords containing it otherwise
+    if value.contains(&[';', '{', '}', '=', '+'][..]) || value.trim() != value {
+        Cow::Owned(format!("{{{}}}", value.replace('}', "}}")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Appends the attribute `key=value;`.
+pub(crate) fn push_attribute(s: &mut String, key: &str, value: &str) {
+    s.push_str(key);
+    s.push('=');
+    s.push_str(&escape(value));
+    s.push(';');
+}
+
+/// Builds a connection string from its attributes.
+pub(crate) fn build<'a>(attributes: impl IntoIterator<Item = (&'a str, &'a str)>) -> String {
+    let mut s = String::new();
+    for (key, value) in attributes {
+        push_attribute(&mut s, key, value);
+    }
+    s
+}
+
+/// Whether the value of the attribute `key` is a secret which must not show up in logs.
+pub(crate) fn is_secret(key: &str) -> bool {
+    let key = key.to_ascii_lowercase();
+    ["pwd", "password", "secret", "token"]
+        .iter()
+        .any(|secret| key.contains(secret))
+}
+
+/// Replaces the values of secret attributes, for `Debug` output.
+pub(crate) fn redact(s: &str) -> String {
+    match parse(s) {
+        Ok(attributes) => build(attributes.iter().map(|(key, value)| {
+            if is_secret(key) {
+                (key.as_str(), "***")
+            } else {
+                (key.as_str(), value.as_str())
+            }
+        })),
+        // Secrets cannot be told apart from the rest, so nothing is shown
+        Err(_) => "***".to_owned(),
+    }
+}
@@ -0,0 +1,394 @@
+use std::fmt::Debug;
+use std::mem::ManuallyDrop;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use futures_channel::oneshot;
+use odbc_api::{
+    handles::{self, slice_to_cow_utf8, SqlChar, SqlResult},
+    sys::{AttrConnectionPooling, AttrCpMatch, AttrOdbcVersion, FetchOrientation},
+};
+use once_cell::sync::OnceCell;
+use sqlx_core::error::Error;
+
+use crate::error::odbc_error_from;
+
+/// The ODBC version an environment declares to the driver manager. It determines the behaviour of
+/// drivers where ODBC versions differ, e.g. the SQLSTATEs they report.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ODBCVersion {
+    V3,
+    #[default]
+    V3_80,
+}
+
+/// How the driver manager pools connections (SQL_ATTR_CONNECTION_POOLING). This is a setting of
+/// the whole process.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ODBCConnectionPooling {
+    /// Leaves the setting as it is, which is off unless changed elsewhere in the process.
+    #[default]
+    Unchanged,
+    Off,
+    /// One pool per driver.
+    OnePerDriver,
+    /// One pool per environment.
+    OnePerEnvironment,
+    /// Lets drivers which support it decide which connections are reused, `OnePerEnvironment`
+    /// for the others.
+    DriverAware,
+}
+
+/// Which pooled connections may be reused for a connection string (SQL_ATTR_CP_MATCH).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ODBCPoolMatch {
+    /// Connection string and connection attributes must match exactly.
+    #[default]
+    Strict,
+    /// The keywords of the connection strings must match, connection attributes need not.
+    Relaxed,
+}
+
+/// Settings for creating an [`ODBCEnvironment`].
+///
+/// ```no_run
+/// use sqlx_odbc::{ODBCConnectionPooling, ODBCEnvironmentOptions, ODBCPoolMatch};
+///
+/// let environment = ODBCEnvironmentOptions::new()
+///     .connection_pooling(ODBCConnectionPooling::DriverAware)
+///     .pool_match(ODBCPoolMatch::Relaxed)
+///     .create()?;
+/// # Ok::<(), sqlx::Error>(())
+/// ```
+#[derive(Debug, Clone, Default)]
+pub struct ODBCEnvironmentOptions {
+    version: ODBCVersion,
+    connection_pooling: ODBCConnectionPooling,
+    pool_match: ODBCPoolMatch,
+}
+
+impl ODBCEnvironmentOptions {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Sets the ODBC version to declare, 3.80 by default.
+    pub fn version(mut self, version: ODBCVersion) -> Self {
+        self.version = version;
+        self
+    }
+
+    /// Sets the connection pooling of the driver manager. It is set when the environment is
+    /// created and applies to environments created afterwards. Pooling requires a thread safe
+    /// driver.
+    pub fn connection_pooling(mut self, pooling: ODBCConnectionPooling) -> Self {
+        self.connection_pooling = pooling;
+        self
+    }
+
+    /// Sets how connections are matched against the pool.
+    pub fn pool_match(mut self, pool_match: ODBCPoolMatch) -> Self {
+        self.pool_match = pool_match;
+        self
+    }
+
+    /// Allocates the environment. Fails with [`Error::Configuration`] if the driver manager
+    /// refuses any of the settings.
+    pub fn create(self) -> Result<ODBCEnvironment, Error> {
+        let pooling = match self.connection_pooling {
+            ODBCConnectionPooling::Unchanged => None,
+            ODBCConnectionPooling::Off => Some(AttrConnectionPooling::Off),
+            ODBCConnectionPooling::OnePerDriver => Some(AttrConnectionPooling::OnePerDriver),
+            ODBCConnectionPooling::OnePerEnvironment => Some(AttrConnectionPooling::OnePerHenv),
+            ODBCConnectionPooling::DriverAware => Some(AttrConnectionPooling::DriverAware),
+        };
+        if let Some(pooling) = pooling {
+            // Safe: connections are only ever used by one thread at a time
+            if unsafe { handles::Environment::set_connection_pooling(pooling) }.is_err() {
+                return Err(configuration_error(format!(
+                    "the driver manager does not support connection pooling {:?}",
+                    self.connection_pooling
+                )));
+            }
+        }
+
+        let mut handle = match handles::Environment::new() {
+            SqlResult::Success(handle) | SqlResult::SuccessWithInfo(handle) => handle,
+            _ => {
+                return Err(configuration_error(
+                    "failed to allocate an ODBC environment, is a driver manager installed?",
+                ))
+            }
+        };
+
+        let version = match self.version {
+            ODBCVersion::V3 => AttrOdbcVersion::Odbc3,
+            ODBCVersion::V3_80 => AttrOdbcVersion::Odbc3_80,
+        };
+        handle
+            .declare_version(version)
+            .into_result(&handle)
+            .map_err(|e| configuration_error(odbc_error_from(e, &handle)))?;
+
+        let pool_match = match self.pool_match {
+            ODBCPoolMatch::Strict => AttrCpMatch::Strict,
+            ODBCPoolMatch::Relaxed => AttrCpMatch::Relaxed,
+        };
+        let res = handle.set_connection_pooling_matching(pool_match);
+        res.into_result(&handle)
+            .map_err(|e| configuration_error(odbc_error_from(e, &handle)))?;
+
+        Ok(ODBCEnvironment {
+            inner: Arc::new(Inner {
+                handle,
+                lock: Mutex::new(()),
+                options: self,
+            }),
+        })
+    }
+}
+
+fn configuration_error(e: impl Into<Box<dyn std::error::Error + Send + Sync>>) -> Error {
+    Error::Configuration(e.into())
+}
+
+/// An ODBC environment, the driver manager's context for connections. Cloning it is cheap, all
+/// clones share the environment. It is freed once the last connection created in it is closed.
+///
+/// Connections use a shared environment with the default [`ODBCEnvironmentOptions`], unless
+/// another one is set with [`ODBCConnectOptions::environment`](crate::ODBCConnectOptions::environment).
+/// ODBC recommends using a single environment per process.
+#[derive(Clone)]
+pub struct ODBCEnvironment {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    handle: handles::Environment,
+    // Diagnostics are read from the handle after a call failed, calls must not interleave.
+    lock: Mutex<()>,
+    options: ODBCEnvironmentOptions,
+}
+
+// Safe: calls which leave diagnostics on the handle take the lock.
+unsafe impl Sync for Inner {}
+
+impl ODBCEnvironment {
+    /// Creates an environment with the default options.
+    pub fn new() -> Result<Self, Error> {
+        ODBCEnvironmentOptions::new().create()
+    }
+
+    /// The environment used by connections which do not have one set. It is created with the
+    /// default options on first use.
+    pub fn shared() -> Result<Self, Error> {
+        static SHARED: OnceCell<ODBCEnvironment> = OnceCell::new();
+        SHARED.get_or_try_init(Self::new).cloned()
+    }
+
+    /// Allocates a connection handle. The handle must not outlive the environment, which is why
+    /// the worker keeps a clone of it next to the handle.
+    pub(crate) fn allocate_connection(&self) -> Result<handles::Connection<'static>, Error> {
+        let _lock = self.inner.lock.lock().unwrap();
+        let handle = &self.inner.handle;
+        let conn = handle
+            .allocate_connection()
+            .into_result(handle)
+            .map_err(|e| odbc_error_from(e, handle))?;
+        Ok(unsafe { handles::Connection::new(ManuallyDrop::new(conn).as_sys()) })
+    }
+
+    /// Lists the drivers the driver manager knows about, e.g. from `odbcinst.ini`. Only drivers
+    /// matching the bitness of the application are listed.
+    pub async fn drivers(&self) -> Result<Vec<ODBCDriverInfo>, Error> {
+        let environment = self.clone();
+        blocking(move || {
+            let drivers = environment.list(
+                FetchOrientation::First,
+                |env, direction| unsafe { env.drivers_buffer_len(direction) },
+                |env, direction, name, attributes| unsafe {
+                    env.drivers_buffer_fill(direction, name, attributes)
+                },
+            )?;
+            Ok(drivers
+                .into_iter()
+                .map(|(name, attributes)| ODBCDriverInfo {
+                    name: until_nul(&name).to_owned(),
+                    attributes: attributes
+                        .split('\0')
+                        .take_while(|attribute| !attribute.is_empty())
+                        .map(|attribute| match attribute.split_once('=') {
+                            Some((key, value)) => (key.to_owned(), value.to_owned()),
+                            None => (attribute.to_owned(), String::new()),
+                        })
+                        .collect(),
+                })
+                .collect())
+        })
+        .await
+    }
+
+    /// Lists the user and system data sources (DSNs), e.g. from `odbc.ini`.
+    pub async fn data_sources(&self) -> Result<Vec<ODBCDataSourceInfo>, Error> {
+        let environment = self.clone();
+        blocking(move || {
+            let mut data_sources = Vec::new();
+            for (first, scope) in [
+                (FetchOrientation::FirstUser, ODBCDataSourceScope::User),
+                (FetchOrientation::FirstSystem, ODBCDataSourceScope::System),
+            ] {
+                let entries = environment.list(
+                    first,
+                    |env, direction| unsafe { env.data_source_buffer_len(direction) },
+                    |env, direction, name, description| unsafe {
+                        env.data_source_buffer_fill(direction, name, description)
+                    },
+                )?;
+                data_sources.extend(entries.into_iter().map(|(name, description)| {
+                    ODBCDataSourceInfo {
+                        name: until_nul(&name).to_owned(),
+                        description: until_nul(&description).to_owned(),
+                        scope,
+                    }
+                }));
+            }
+            Ok(data_sources)
+        })
+        .await
+    }
+
+    /// Walks the list SQLDrivers or SQLDataSources report, starting at `first`. Each entry
+    /// consists of two strings. The first pass finds the buffer sizes needed, the second one
+    /// fetches the entries.
+    fn list(
+        &self,
+        first: FetchOrientation,
+        len: impl Fn(&handles::Environment, FetchOrientation) -> SqlResult<(i16, i16)>,
+        fill: impl Fn(
+            &handles::Environment,
+            FetchOrientation,
+            &mut [SqlChar],
+            &mut [SqlChar],
+        ) -> SqlResult<()>,
+    ) -> Result<Vec<(String, String)>, Error> {
+        // The position in the list is state of the handle
+        let _lock = self.inner.lock.lock().unwrap();
+        let env = &self.inner.handle;
+        let result = |res: SqlResult<()>| {
+            res.into_result_bool(env)
+                .map_err(|e| odbc_error_from(e, env))
+        };
+
+        let (mut len1, mut len2) = (0, 0);
+        let mut direction = first;
+        while let Some((l1, l2)) = len(env, direction)
+            .into_result_option(env)
+            .map_err(|e| odbc_error_from(e, env))?
+        {
+            len1 = len1.max(l1);
+            len2 = len2.max(l2);
+            direction = FetchOrientation::Next;
+        }
+
+        // Lengths exclude the terminating zero, lists of attributes end with two of them
+        let mut buf1 = vec![0; len1 as usize + 2];
+        let mut buf2 = vec![0; len2 as usize + 2];
+        let mut entries = Vec::new();
+        let mut direction = first;
+        while result(fill(env, direction, &mut buf1, &mut buf2))? {
+            entries.push((
+                slice_to_cow_utf8(&buf1).into_owned(),
+                slice_to_cow_utf8(&buf2).into_owned(),
+            ));
+            buf1.fill(0);
+            buf2.fill(0);
+            direction = FetchOrientation::Next;
+        }
+        Ok(entries)
+    }
+}
+
+/// Runs blocking ODBC calls on a thread of their own.
+async fn blocking<T: Send + 'static>(
+    f: impl FnOnce() -> Result<T, Error> + Send + 'static,
+) -> Result<T, Error> {
+    let (tx, rx) = oneshot::channel();
+    thread::Builder::new()
+        .name("sqlx-odbc-environment".to_owned())
+        .spawn(move || tx.send(f()))?;
+    rx.await.map_err(|_| Error::WorkerCrashed)?
+}
+
+/// The part of a zero terminated string before the terminating zero.
+fn until_nul(s: &str) -> &str {
+    s.split('\0').next().unwrap_or_default()
+}
+
+/// An installed ODBC driver, as listed by [`ODBCEnvironment::drivers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ODBCDriverInfo {
+    name: String,
+    attributes: Vec<(String, String)>,
+}
+
+impl ODBCDriverInfo {
+    /// The name to use in the `Driver` attribute of a connection string.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The attributes of the driver's entry in the driver manager's configuration, e.g.
+    /// `Driver` (the path of the library), `Setup` or `UsageCount`.
+    pub fn attributes(&self) -> &[(String, String)] {
+        &self.attributes
+    }
+
+    /// The value of the attribute `key`, which is case insensitive.
+    pub fn attribute(&self, key: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(k, _)| k.eq_ignore_ascii_case(key))
+            .map(|(_, v)| v.as_str())
+    }
+}
+
+/// Whether a data source is visible to the current user only or to everybody.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ODBCDataSourceScope {
+    User,
+    System,
+}
+
+/// A data source (DSN), as listed by [`ODBCEnvironment::data_sources`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ODBCDataSourceInfo {
+    name: String,
+    description: String,
+    scope: ODBCDataSourceScope,
+}
+
+impl ODBCDataSourceInfo {
+    /// The name to use in the `DSN` attribute of a connection string.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The description of the data source, usually the name of its driver.
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn scope(&self) -> ODBCDataSourceScope {
+        self.scope
+    }
+}
+
+impl Debug for ODBCEnvironment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ODBCEnvironment")
+            .field("version", &self.inner.options.version)
+            .field("connection_pooling", &self.inner.options.connection_pooling)
+            .field("pool_match", &self.inner.options.pool_match)
+            .finish()
+    }
+}
@@ -0,0 +1,226 @@
+use std::{borrow::Cow, error::Error as StdError, fmt::Display};
+
+use odbc_api::{
+    handles::{slice_to_cow_utf8, AsHandle, Record},
+    sys::{HandleType, HeaderDiagnosticIdentifier, SQLGetDiagFieldW, SqlReturn},
+};
+use sqlx_core::error::{DatabaseError, Error, ErrorKind};
+
+/// A diagnostic record reported by the driver manager or the driver.
+#[derive(Debug, Clone)]
+pub struct ODBCDiagnosticRecord {
+    sqlstate: String,
+    native_error: i32,
+    message: String,
+    row_number: Option<i64>,
+    column_number: Option<i32>,
+}
+
+impl ODBCDiagnosticRecord {
+    /// The five character SQLSTATE. The first two characters are the class, the remaining three
+    /// the subclass.
+    pub fn sqlstate(&self) -> &str {
+        &self.sqlstate
+    }
+
+    /// The error code of the data source.
+    pub fn native_error(&self) -> i32 {
+        self.native_error
+    }
+
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The row in the row set or parameter set the record refers to, if known.
+    pub fn row_number(&self) -> Option<i64> {
+        self.row_number
+    }
+
+    /// The column in the result set or parameter the record refers to, if known.
+    pub fn column_number(&self) -> Option<i32> {
+        self.column_number
+    }
+
+    fn from_record(record: &Record) -> Self {
+        ODBCDiagnosticRecord {
+            sqlstate: record.state.as_str().to_owned(),
+            native_error: record.native_error,
+            message: slice_to_cow_utf8(&record.message).into_owned(),
+            row_number: None,
+            column_number: None,
+        }
+    }
+}
+
+/// An error returned by the driver manager or the driver, together with all of its diagnostic
+/// records. The first record is the most important one.
+#[derive(Debug)]
+pub struct ODBCDatabaseError {
+    function: &'static str,
+    records: Vec<ODBCDiagnosticRecord>,
+}
+
+impl ODBCDatabaseError {
+    /// The ODBC function which failed, e.g. `SQLExecDirect`.
+    pub fn function(&self) -> &'static str {
+        self.function
+    }
+
+    pub fn records(&self) -> &[ODBCDiagnosticRecord] {
+        &self.records
+    }
+
+    fn first(&self) -> &ODBCDiagnosticRecord {
+        &self.records[0]
+    }
+
+    /// Reads all diagnostic records left on `handle` by the last call.
+    fn from_handle(handle: &impl AsHandle, function: &'static str) -> Option<Self> {
+        let mut records = Vec::new();
+        let mut record = Record::with_capacity(512);
+        let mut rec_number = 1;
+        while record.fill_from(handle, rec_number) {
+            let mut r = ODBCDiagnosticRecord::from_record(&record);
+            if handle.handle_type() == HandleType::Stmt {
+                r.row_number =
+                    diag_field::<isize>(handle, rec_number, HeaderDiagnosticIdentifier::RowNumber)
+                        .map(|n| n as i64);
+                r.column_number =
+                    diag_field::<i32>(handle, rec_number, HeaderDiagnosticIdentifier::ColumnNumber);
+            }
+            records.push(r);
+            rec_number += 1;
+        }
+        (!records.is_empty()).then_some(ODBCDatabaseError { function, records })
+    }
+}
+
+/// Reads a numeric field of a diagnostic record. Negative values are used by ODBC to signal that
+/// the number is unknown or does not apply.
+fn diag_field<T>(
+    handle: &impl AsHandle,
+    rec_number: i16,
+    identifier: HeaderDiagnosticIdentifier,
+) -> Option<T>
+where
+    T: Default + PartialOrd,
+{
+    let mut value = T::default();
+    let ret = unsafe {
+        SQLGetDiagFieldW(
+            handle.handle_type(),
+            handle.as_handle(),
+            rec_number,
+            identifier as i16,
+            &mut value as *mut T as *mut _,
+            0,
+            std::ptr::null_mut(),
+        )
+    };
+    (ret == SqlReturn::SUCCESS && value > T::default()).then_some(value)
+}
+
+impl Display for ODBCDatabaseError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let first = self.first();
+        write!(
+            f,
+            "{} failed: [{}] {}",
+            self.function, first.sqlstate, first.message
+        )
+    }
+}
+
+impl StdError for ODBCDatabaseError {}
+
+impl DatabaseError for ODBCDatabaseError {
+    fn message(&self) -> &str {
+        &self.first().message
+    }
+
+    fn code(&self) -> Option<Cow<'_, str>> {
+        Some(Cow::Borrowed(&self.first().sqlstate))
+    }
+
+    fn as_error(&self) -> &(dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn as_error_mut(&mut self) -> &mut (dyn StdError + Send + Sync + 'static) {
+        self
+    }
+
+    fn into_error(self: Box<Self>) -> Box<dyn StdError + Send + Sync + 'static> {
+        self
+    }
+
+    fn kind(&self) -> ErrorKind {
+        let first = self.first();
+        match first.sqlstate.as_str() {
+            "23505" => ErrorKind::UniqueViolation,
+            "23503" => ErrorKind::ForeignKeyViolation,
+            "23502" => ErrorKind::NotNullViolation,
+            "23514" => ErrorKind::CheckViolation,
+            // Some data sources report every integrity constraint violation with the generic
+            // class code, the native error tells them apart.
+            "23000" => match first.native_error {
+                // SQL Server
+                2601 | 2627 => ErrorKind::UniqueViolation,
+                547 => ErrorKind::ForeignKeyViolation,
+                515 => ErrorKind::NotNullViolation,
+                // MySQL / MariaDB
+                1062 | 1586 => ErrorKind::UniqueViolation,
+                1216 | 1217 | 1451 | 1452 => ErrorKind::ForeignKeyViolation,
+                1048 => ErrorKind::NotNullViolation,
+                3819 => ErrorKind::CheckViolation,
+                _ => ErrorKind::Other,
+            },
+            _ => ErrorKind::Other,
+        }
+    }
+}
+
+/// Converts an error of `odbc_api`. Only the first diagnostic record is available this way, use
+/// [`odbc_error_from`] if the handle the error occurred on is still at hand.
+pub(crate) fn odbc_error(e: odbc_api::Error) -> Error {
+    match e {
+        odbc_api::Error::Diagnostics { record, function } => {
+            Error::Database(Box::new(ODBCDatabaseError {
+                function,
+                records: vec![ODBCDiagnosticRecord::from_record(&record)],
+            }))
+        }
+        e => Error::AnyDriverError(Box::new(e)),
+    }
+}
+
+/// Converts an error of `odbc_api` which occurred on `handle`, collecting all of its diagnostic
+/// records. Must be called before the handle is used again.
+pub(crate) fn odbc_error_from(e: odbc_api::Error, handle: &impl AsHandle) -> Error {
+    match e {
+        odbc_api::Error::Diagnostics { function, .. } => {
+            match ODBCDatabaseError::from_handle(handle, function) {
+                Some(err) => Error::Database(Box::new(err)),
+                None => odbc_error(e),
+            }
+        }
+        e => odbc_error(e),
+    }
+}
+
+/// Checks the return code of an ODBC function called directly through `odbc_sys`, for what
+/// `odbc_api` has no method.
+pub(crate) fn check(
+    ret: SqlReturn,
+    function: &'static str,
+    handle: &impl AsHandle,
+) -> Result<(), Error> {
+    match ret {
+        SqlReturn::SUCCESS | SqlReturn::SUCCESS_WITH_INFO => Ok(()),
+        _ => Err(match ODBCDatabaseError::from_handle(handle, function) {
+            Some(err) => Error::Database(Box::new(err)),
+            None => odbc_error(odbc_api::Error::NoDiagnostics { function }),
+        }),
+    }
+}
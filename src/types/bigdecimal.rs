@@ -0,0 +1,44 @@
+use bigdecimal::BigDecimal;
+use odbc_api::DataType;
+use sqlx::{encode, Decode, Encode, Type};
+use sqlx_core::{database::HasArguments, error::BoxDynError};
+
+use crate::{mismatched_types, text_of, ODBCTypeInfo, ODBCValue, ODBCValueOpt, ODBCValueRef, ODBC};
+
+impl Type<ODBC> for BigDecimal {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Decimal {
+            precision: 38,
+            scale: 0,
+        })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        ty.is_exact_numeric() || ty.is_integer() || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for BigDecimal {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Numeric(x, _) | ODBCValue::String(x) => Ok(text_of(x)?.parse()?),
+            v => match v.integer() {
+                Some(int) => Ok(int?.into()),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for BigDecimal {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        // Without an exponent, which drivers do not accept for DECIMAL
+        buf.push(ODBCValueOpt::Value(ODBCValue::numeric(
+            self.to_plain_string(),
+        )));
+        encode::IsNull::No
+    }
+}
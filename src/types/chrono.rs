@@ -0,0 +1,200 @@
+use chrono::{DateTime, Datelike, NaiveDate, NaiveDateTime, NaiveTime, Timelike, Utc};
+use odbc_api::{
+    parameter::VarCharBox,
+    sys::{Date, Time, Timestamp},
+    DataType,
+};
+use sqlx::{encode, Decode, Encode, Type};
+use sqlx_core::{database::HasArguments, error::BoxDynError};
+
+use super::timestamp_value;
+use crate::{mismatched_types, ODBCTypeInfo, ODBCValue, ODBCValueOpt, ODBCValueRef, ODBC};
+
+fn to_date(date: &NaiveDate) -> Date {
+    Date {
+        year: date.year() as i16,
+        month: date.month() as u16,
+        day: date.day() as u16,
+    }
+}
+
+fn from_date(date: &Date) -> Result<NaiveDate, BoxDynError> {
+    NaiveDate::from_ymd_opt(date.year.into(), date.month.into(), date.day.into())
+        .ok_or_else(|| format!("invalid date {date:?}").into())
+}
+
+fn from_time(time: &Time) -> Result<NaiveTime, BoxDynError> {
+    NaiveTime::from_hms_opt(time.hour.into(), time.minute.into(), time.second.into())
+        .ok_or_else(|| format!("invalid time {time:?}").into())
+}
+
+fn to_timestamp(datetime: &NaiveDateTime) -> Timestamp {
+    Timestamp {
+        year: datetime.year() as i16,
+        month: datetime.month() as u16,
+        day: datetime.day() as u16,
+        hour: datetime.hour() as u16,
+        minute: datetime.minute() as u16,
+        second: datetime.second() as u16,
+        // chrono represents leap seconds as a second fraction
+        fraction: datetime.nanosecond().min(999_999_999),
+    }
+}
+
+fn from_timestamp(timestamp: &Timestamp) -> Result<NaiveDateTime, BoxDynError> {
+    NaiveDate::from_ymd_opt(
+        timestamp.year.into(),
+        timestamp.month.into(),
+        timestamp.day.into(),
+    )
+    .and_then(|date| {
+        date.and_hms_nano_opt(
+            timestamp.hour.into(),
+            timestamp.minute.into(),
+            timestamp.second.into(),
+            timestamp.fraction,
+        )
+    })
+    .ok_or_else(|| format!("invalid timestamp {timestamp:?}").into())
+}
+
+/// Parses a timestamp in the format of SQL, or ISO 8601 with a `T`.
+fn parse_timestamp(s: &str) -> Result<NaiveDateTime, BoxDynError> {
+    NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S%.f")
+        .or_else(|_| s.parse())
+        .map_err(Into::into)
+}
+
+impl Type<ODBC> for NaiveDate {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Date)
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Date) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for NaiveDate {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Date(d) => from_date(d),
+            v => match v.text() {
+                Some(s) => Ok(s?.parse()?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for NaiveDate {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        buf.push(ODBCValueOpt::Value(ODBCValue::Date(to_date(self))));
+        encode::IsNull::No
+    }
+}
+
+impl Type<ODBC> for NaiveTime {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Time { precision: 0 })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Time { precision: _ }) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for NaiveTime {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Time(t) => from_time(t),
+            v => match v.text() {
+                Some(s) => Ok(s?.parse()?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for NaiveTime {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        let value = if self.nanosecond() == 0 {
+            ODBCValue::Time(Time {
+                hour: self.hour() as u16,
+                minute: self.minute() as u16,
+                second: self.second() as u16,
+            })
+        } else {
+            // SQL_TIME_STRUCT would drop the fractional seconds, the driver converts the text
+            ODBCValue::String(VarCharBox::from_string(
+                self.format("%H:%M:%S%.f").to_string(),
+            ))
+        };
+        buf.push(ODBCValueOpt::Value(value));
+        encode::IsNull::No
+    }
+}
+
+impl Type<ODBC> for NaiveDateTime {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Timestamp { precision: 9 })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Timestamp { precision: _ }) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for NaiveDateTime {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Timestamp(t, _) => from_timestamp(t),
+            v => match v.text() {
+                Some(s) => parse_timestamp(s?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for NaiveDateTime {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        buf.push(ODBCValueOpt::Value(timestamp_value(to_timestamp(self))));
+        encode::IsNull::No
+    }
+}
+
+/// Stored as a timestamp in UTC, ODBC has no type with a time zone.
+impl Type<ODBC> for DateTime<Utc> {
+    fn type_info() -> ODBCTypeInfo {
+        NaiveDateTime::type_info()
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        NaiveDateTime::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for DateTime<Utc> {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(NaiveDateTime::decode(value)?.and_utc())
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for DateTime<Utc> {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        self.naive_utc().encode_by_ref(buf)
+    }
+}
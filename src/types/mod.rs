@@ -0,0 +1,26 @@
+//! Conversions for types from other crates, each behind the feature of the same name.
+
+#[cfg(feature = "bigdecimal")]
+mod bigdecimal;
+#[cfg(feature = "chrono")]
+mod chrono;
+#[cfg(feature = "rust_decimal")]
+mod rust_decimal;
+#[cfg(feature = "time")]
+mod time;
+#[cfg(feature = "uuid")]
+mod uuid;
+
+/// A timestamp with as many digits of fractional seconds as it needs.
+#[cfg(any(feature = "chrono", feature = "time"))]
+// `u32::is_multiple_of` needs Rust 1.87
+#[allow(clippy::manual_is_multiple_of)]
+fn timestamp_value(value: odbc_api::sys::Timestamp) -> crate::ODBCValue {
+    let mut fraction = value.fraction;
+    let mut precision = 9;
+    while precision > 0 && fraction % 10 == 0 {
+        fraction /= 10;
+        precision -= 1;
+    }
+    crate::ODBCValue::Timestamp(value, precision)
+}
@@ -0,0 +1,44 @@
+use odbc_api::DataType;
+use rust_decimal::Decimal;
+use sqlx::{encode, Decode, Encode, Type};
+use sqlx_core::{database::HasArguments, error::BoxDynError};
+
+use crate::{mismatched_types, text_of, ODBCTypeInfo, ODBCValue, ODBCValueOpt, ODBCValueRef, ODBC};
+
+impl Type<ODBC> for Decimal {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Decimal {
+            precision: 28,
+            scale: 0,
+        })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        ty.is_exact_numeric() || ty.is_integer() || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for Decimal {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            // Fails instead of rounding if there are more digits than fit
+            ODBCValue::Numeric(x, _) | ODBCValue::String(x) => {
+                Ok(Decimal::from_str_exact(text_of(x)?)?)
+            }
+            v => match v.integer() {
+                Some(int) => Ok(Decimal::try_from_i128_with_scale(int?, 0)?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for Decimal {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        buf.push(ODBCValueOpt::Value(ODBCValue::numeric(self.to_string())));
+        encode::IsNull::No
+    }
+}
@@ -0,0 +1,213 @@
+use odbc_api::{
+    parameter::VarCharBox,
+    sys::{Date as OdbcDate, Time as OdbcTime, Timestamp},
+    DataType,
+};
+use sqlx::{encode, Decode, Encode, Type};
+use sqlx_core::{database::HasArguments, error::BoxDynError};
+use time::{
+    format_description::FormatItem, macros::format_description, Date, Month, OffsetDateTime,
+    PrimitiveDateTime, Time, UtcOffset,
+};
+
+use super::timestamp_value;
+use crate::{mismatched_types, ODBCTypeInfo, ODBCValue, ODBCValueOpt, ODBCValueRef, ODBC};
+
+const DATE_FORMAT: &[FormatItem<'_>] = format_description!("[year]-[month]-[day]");
+
+const TIME_FORMAT: &[FormatItem<'_>] =
+    format_description!("[hour]:[minute]:[second][optional [.[subsecond]]]");
+
+/// The format of SQL, or ISO 8601 with a `T`.
+const TIMESTAMP_FORMAT: &[FormatItem<'_>] = format_description!(
+    "[year]-[month]-[day][first [ ][T]][hour]:[minute]:[second][optional [.[subsecond]]]"
+);
+
+fn to_date(date: &Date) -> OdbcDate {
+    OdbcDate {
+        year: date.year() as i16,
+        month: u8::from(date.month()).into(),
+        day: date.day().into(),
+    }
+}
+
+fn from_date(date: &OdbcDate) -> Result<Date, BoxDynError> {
+    let month = Month::try_from(u8::try_from(date.month)?)?;
+    Ok(Date::from_calendar_date(
+        date.year.into(),
+        month,
+        u8::try_from(date.day)?,
+    )?)
+}
+
+fn from_time(time: &OdbcTime) -> Result<Time, BoxDynError> {
+    Ok(Time::from_hms(
+        u8::try_from(time.hour)?,
+        u8::try_from(time.minute)?,
+        u8::try_from(time.second)?,
+    )?)
+}
+
+fn to_timestamp(datetime: &PrimitiveDateTime) -> Timestamp {
+    let OdbcDate { year, month, day } = to_date(&datetime.date());
+    Timestamp {
+        year,
+        month,
+        day,
+        hour: datetime.hour().into(),
+        minute: datetime.minute().into(),
+        second: datetime.second().into(),
+        fraction: datetime.nanosecond(),
+    }
+}
+
+fn from_timestamp(timestamp: &Timestamp) -> Result<PrimitiveDateTime, BoxDynError> {
+    let date = from_date(&OdbcDate {
+        year: timestamp.year,
+        month: timestamp.month,
+        day: timestamp.day,
+    })?;
+    let time = Time::from_hms_nano(
+        u8::try_from(timestamp.hour)?,
+        u8::try_from(timestamp.minute)?,
+        u8::try_from(timestamp.second)?,
+        timestamp.fraction,
+    )?;
+    Ok(PrimitiveDateTime::new(date, time))
+}
+
+impl Type<ODBC> for Date {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Date)
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Date) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for Date {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Date(d) => from_date(d),
+            v => match v.text() {
+                Some(s) => Ok(Date::parse(s?, DATE_FORMAT)?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for Date {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        buf.push(ODBCValueOpt::Value(ODBCValue::Date(to_date(self))));
+        encode::IsNull::No
+    }
+}
+
+impl Type<ODBC> for Time {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Time { precision: 0 })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Time { precision: _ }) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for Time {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Time(t) => from_time(t),
+            v => match v.text() {
+                Some(s) => Ok(Time::parse(s?, TIME_FORMAT)?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for Time {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        let value = if self.nanosecond() == 0 {
+            ODBCValue::Time(OdbcTime {
+                hour: self.hour().into(),
+                minute: self.minute().into(),
+                second: self.second().into(),
+            })
+        } else {
+            // SQL_TIME_STRUCT would drop the fractional seconds, the driver converts the text
+            let text = self
+                .format(TIME_FORMAT)
+                .expect("times can always be formatted");
+            ODBCValue::String(VarCharBox::from_string(text))
+        };
+        buf.push(ODBCValueOpt::Value(value));
+        encode::IsNull::No
+    }
+}
+
+impl Type<ODBC> for PrimitiveDateTime {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Timestamp { precision: 9 })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(ty.0, DataType::Timestamp { precision: _ }) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for PrimitiveDateTime {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Timestamp(t, _) => from_timestamp(t),
+            v => match v.text() {
+                Some(s) => Ok(PrimitiveDateTime::parse(s?, TIMESTAMP_FORMAT)?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for PrimitiveDateTime {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        buf.push(ODBCValueOpt::Value(timestamp_value(to_timestamp(self))));
+        encode::IsNull::No
+    }
+}
+
+/// Stored as a timestamp in UTC, ODBC has no type with a time zone. Decoded values are in UTC.
+impl Type<ODBC> for OffsetDateTime {
+    fn type_info() -> ODBCTypeInfo {
+        PrimitiveDateTime::type_info()
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        PrimitiveDateTime::compatible(ty)
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for OffsetDateTime {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        Ok(PrimitiveDateTime::decode(value)?.assume_utc())
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for OffsetDateTime {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        let utc = self.to_offset(UtcOffset::UTC);
+        PrimitiveDateTime::new(utc.date(), utc.time()).encode_by_ref(buf)
+    }
+}
@@ -0,0 +1,64 @@
+use odbc_api::{
+    sys::{Guid, SqlDataType},
+    DataType,
+};
+use sqlx::{encode, Decode, Encode, Type};
+use sqlx_core::{database::HasArguments, error::BoxDynError};
+use uuid::Uuid;
+
+use crate::{mismatched_types, ODBCTypeInfo, ODBCValue, ODBCValueOpt, ODBCValueRef, ODBC};
+
+/// Bound as SQL_GUID. Besides GUID columns, UUIDs can be decoded from 16 bytes of binary data in
+/// RFC 4122 order or from their text.
+impl Type<ODBC> for Uuid {
+    fn type_info() -> ODBCTypeInfo {
+        ODBCTypeInfo(DataType::Other {
+            data_type: SqlDataType::EXT_GUID,
+            column_size: 36,
+            decimal_digits: 0,
+        })
+    }
+
+    fn compatible(ty: &ODBCTypeInfo) -> bool {
+        matches!(
+            ty.0,
+            DataType::Other {
+                data_type: SqlDataType::EXT_GUID,
+                ..
+            } | DataType::Binary { length: 16 }
+                | DataType::Varbinary { length: _ }
+        ) || ty.is_text()
+    }
+}
+
+impl<'r> Decode<'r, ODBC> for Uuid {
+    fn decode(value: ODBCValueRef<'r>) -> Result<Self, BoxDynError> {
+        match value.value()? {
+            ODBCValue::Guid(g) => Ok(Uuid::from_fields(g.d1, g.d2, g.d3, &g.d4)),
+            ODBCValue::Binary(x) => match x.as_bytes() {
+                None => Err(Box::new(sqlx_core::error::UnexpectedNullError)),
+                Some(b) => Ok(Uuid::from_slice(b)?),
+            },
+            v => match v.text() {
+                Some(s) => Ok(Uuid::parse_str(s?)?),
+                None => Err(mismatched_types::<Self>(v)),
+            },
+        }
+    }
+}
+
+impl<'r> Encode<'r, ODBC> for Uuid {
+    fn encode_by_ref(
+        &self,
+        buf: &mut <ODBC as HasArguments<'r>>::ArgumentBuffer,
+    ) -> encode::IsNull {
+        let (d1, d2, d3, d4) = self.as_fields();
+        buf.push(ODBCValueOpt::Value(ODBCValue::Guid(Guid {
+            d1,
+            d2,
+            d3,
+            d4: *d4,
+        })));
+        encode::IsNull::No
+    }
+}
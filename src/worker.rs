@@ -0,0 +1,1298 @@
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::io;
+use std::mem::ManuallyDrop;
+use std::pin::Pin;
+use std::ptr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use std::thread;
+use std::time::Duration;
+
+use futures_channel::oneshot;
+use futures_core::Stream;
+use futures_util::StreamExt;
+use odbc_api::{
+    buffers::{AnySlice, BufferDesc, ColumnarAnyBuffer},
+    handles::{
+        self, slice_to_cow_utf8, AsStatementRef, SqlResult, SqlText, Statement, StatementImpl,
+        StatementRef,
+    },
+    parameter::{VarBinaryBox, VarCharBox},
+    sys::{
+        ConnectionAttribute, Date, FreeStmtOption, Pointer, SQLFreeStmt, SQLGetConnectAttrW,
+        SQLSetConnectAttrW, SQLSetStmtAttrW, SqlDataType, StatementAttribute, Time, Timestamp,
+    },
+    Bit, BlockCursor, ColumnDescription, Cursor, CursorImpl, CursorRow, DataType, Nullability,
+    Nullable, ParameterCollectionRef,
+};
+use sqlx_core::{
+    describe::Describe,
+    error::{BoxDynError, Error},
+    ext::ustr::UStr,
+    Either, HashMap,
+};
+
+use crate::{
+    cancel::{CancelHandle, CancelOnDrop, RunningStatement},
+    error::{check, odbc_error, odbc_error_from},
+    ODBCArguments, ODBCColumn, ODBCConnectOptions, ODBCEnvironment, ODBCQueryOptions,
+    ODBCQueryResult, ODBCRow, ODBCStatement, ODBCTypeInfo, ODBCValue, ODBCValueOpt,
+    TransactionSettings, ODBC,
+};
+
+// Each ODBC connection has a dedicated thread. ODBC calls block until the driver is done, and
+// handles must not be shared between threads, so the connection and every statement created from
+// it live on that thread. The async side talks to it through `Command`s.
+
+static THREAD_ID: AtomicUsize = AtomicUsize::new(0);
+
+/// Number of rows which may be buffered in the channel between the worker and a result stream.
+/// Once it is full the worker waits for the stream to catch up.
+const ROW_CHANNEL_SIZE: usize = 50;
+
+/// Upper bound for the size of a single value in a bound column buffer. Result sets with columns
+/// which may hold larger (or unbounded) values are fetched row by row instead.
+const MAX_BOUND_VALUE_SIZE: usize = 8192;
+
+/// Prepared statements of a connection, by SQL.
+type StatementCache<'c> = sqlx_core::common::StatementCache<PreparedStatement<'c>>;
+
+/// A statement in the `StatementCache`.
+struct PreparedStatement<'c> {
+    handle: StatementImpl<'c>,
+    /// The query timeout set on the handle, in seconds.
+    query_timeout: u32,
+}
+
+type RowSender = flume::Sender<Result<Either<ODBCQueryResult, ODBCRow>, Error>>;
+
+/// The results of an execution, in order. Dropping it before it is done cancels the statement.
+pub(crate) struct Execution {
+    rx: flume::r#async::RecvStream<'static, Result<Either<ODBCQueryResult, ODBCRow>, Error>>,
+    _cancel: CancelOnDrop,
+}
+
+impl Stream for Execution {
+    type Item = Result<Either<ODBCQueryResult, ODBCRow>, Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.rx.poll_next_unpin(cx)
+    }
+}
+
+/// An execution as the worker sees it, with the connection defaults applied to its options.
+struct ExecutionContext<'a> {
+    running: &'a RunningStatement,
+    execution: u64,
+    query_timeout: u32,
+    batch_size: usize,
+    tx: &'a RowSender,
+}
+
+pub(crate) struct ConnectionWorker {
+    command_tx: flume::Sender<Command>,
+    running: Arc<RunningStatement>,
+}
+
+enum Command {
+    Execute {
+        sql: Box<str>,
+        arguments: ODBCArguments,
+        persistent: bool,
+        options: ODBCQueryOptions,
+        execution: u64,
+        tx: RowSender,
+    },
+    Prepare {
+        sql: Box<str>,
+        tx: oneshot::Sender<Result<ODBCStatement<'static>, Error>>,
+    },
+    Describe {
+        sql: Box<str>,
+        tx: oneshot::Sender<Result<Describe<ODBC>, Error>>,
+    },
+    Ping {
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+    // `depth` is the number of levels enclosing the one which begins or ends, savepoints are
+    // used unless it is 0.
+    Begin {
+        depth: usize,
+        settings: TransactionSettings,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+    Commit {
+        depth: usize,
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+    Rollback {
+        depth: usize,
+        tx: Option<oneshot::Sender<Result<(), Error>>>,
+    },
+    Close {
+        tx: oneshot::Sender<Result<(), Error>>,
+    },
+}
+
+impl ConnectionWorker {
+    pub(crate) async fn establish(options: ODBCConnectOptions) -> Result<Self, Error> {
+        let (establish_tx, establish_rx) = oneshot::channel();
+
+        thread::Builder::new()
+            .name(format!(
+                "sqlx-odbc-worker-{}",
+                THREAD_ID.fetch_add(1, Ordering::AcqRel)
+            ))
+            .spawn(move || {
+                let (command_tx, command_rx) = flume::unbounded();
+                let running = Arc::new(RunningStatement::default());
+
+                let conn = match Connection::establish(&options) {
+                    Ok(conn) => conn,
+                    Err(e) => {
+                        establish_tx.send(Err(e)).ok();
+                        return;
+                    }
+                };
+
+                let worker = Self {
+                    command_tx,
+                    running: Arc::clone(&running),
+                };
+                if establish_tx.send(Ok(worker)).is_err() {
+                    return;
+                }
+
+                let dbms = conn.dbms_name().unwrap_or_default().to_ascii_lowercase();
+                let validation_query = match &options.validation_query {
+                    Some(sql) => sql.clone(),
+                    None => default_validation_query(&dbms).to_owned(),
+                };
+                let savepoints = SavepointDialect::of(&dbms);
+
+                // Ends once the `ConnectionWorker` is dropped, which drops and thereby
+                // disconnects `conn`, or when it is closed. Cached statements go first, as they
+                // borrow it.
+                let mut statements = StatementCache::new(options.statement_cache_capacity);
+                let mut close_tx = None;
+
+                for cmd in command_rx {
+                    match cmd {
+                        Command::Execute {
+                            sql,
+                            arguments,
+                            persistent,
+                            options: query_options,
+                            execution,
+                            tx,
+                        } => {
+                            if tx.is_disconnected() {
+                                // Dropped before it got its turn
+                                continue;
+                            }
+                            let ctx = ExecutionContext {
+                                running: &running,
+                                execution,
+                                query_timeout: query_options
+                                    .query_timeout
+                                    .or(options.query_timeout)
+                                    .map_or(0, timeout_secs),
+                                batch_size: query_options.batch_size.unwrap_or(options.batch_size),
+                                tx: &tx,
+                            };
+                            let res = if persistent && statements.is_enabled() {
+                                execute_prepared(&conn, &mut statements, &sql, &arguments, &ctx)
+                            } else {
+                                execute(&conn, &sql, &arguments, &ctx)
+                            };
+                            if let Err(e) = res {
+                                tx.send(Err(e)).ok();
+                            }
+                        }
+                        Command::Prepare { sql, tx } => {
+                            tx.send(prepare(&conn, &mut statements, &sql)).ok();
+                        }
+                        Command::Describe { sql, tx } => {
+                            tx.send(describe(&conn, &mut statements, &sql)).ok();
+                        }
+                        Command::Ping { tx } => {
+                            let query_timeout = options.query_timeout.map_or(0, timeout_secs);
+                            tx.send(conn.ping(&validation_query, query_timeout)).ok();
+                        }
+                        Command::Begin {
+                            depth,
+                            settings,
+                            tx,
+                        } => {
+                            let res = match depth {
+                                0 => conn.begin(settings),
+                                _ => conn.execute_sql(&savepoints.begin(depth)),
+                            };
+                            tx.send(res).ok();
+                        }
+                        Command::Commit { depth, tx } => {
+                            let res = match depth {
+                                0 => conn.end_transaction(true),
+                                _ => match savepoints.release(depth) {
+                                    Some(sql) => conn.execute_sql(&sql),
+                                    None => Ok(()),
+                                },
+                            };
+                            tx.send(res).ok();
+                        }
+                        Command::Rollback { depth, tx } => {
+                            let res = match depth {
+                                0 => conn.end_transaction(false),
+                                _ => conn.execute_sql(&savepoints.rollback(depth)),
+                            };
+                            if let Some(tx) = tx {
+                                tx.send(res).ok();
+                            }
+                        }
+                        Command::Close { tx } => {
+                            close_tx = Some(tx);
+                            break;
+                        }
+                    }
+                }
+
+                if let Some(tx) = close_tx {
+                    drop(statements);
+                    tx.send(conn.close()).ok();
+                }
+            })?;
+
+        establish_rx.await.map_err(|_| Error::WorkerCrashed)?
+    }
+
+    pub(crate) fn execute(
+        &self,
+        sql: &str,
+        arguments: ODBCArguments,
+        persistent: bool,
+        options: ODBCQueryOptions,
+    ) -> Result<Execution, Error> {
+        let (tx, rx) = flume::bounded(ROW_CHANNEL_SIZE);
+        let execution = self.running.next_execution();
+
+        self.command_tx
+            .send(Command::Execute {
+                sql: sql.into(),
+                arguments,
+                persistent,
+                options,
+                execution,
+                tx,
+            })
+            .map_err(|_| Error::WorkerCrashed)?;
+
+        Ok(Execution {
+            rx: rx.into_stream(),
+            _cancel: CancelOnDrop {
+                running: Arc::clone(&self.running),
+                execution,
+            },
+        })
+    }
+
+    pub(crate) fn cancel_handle(&self) -> CancelHandle {
+        CancelHandle {
+            running: Arc::clone(&self.running),
+        }
+    }
+
+    pub(crate) async fn prepare(&self, sql: &str) -> Result<ODBCStatement<'static>, Error> {
+        self.request(|tx| Command::Prepare {
+            sql: sql.into(),
+            tx,
+        })
+        .await
+    }
+
+    pub(crate) async fn describe(&self, sql: &str) -> Result<Describe<ODBC>, Error> {
+        self.request(|tx| Command::Describe {
+            sql: sql.into(),
+            tx,
+        })
+        .await
+    }
+
+    /// Checks that the connection is alive and runs the validation query on it.
+    pub(crate) async fn ping(&self) -> Result<(), Error> {
+        self.request(|tx| Command::Ping { tx }).await
+    }
+
+    /// Starts a transaction by turning off autocommit if `depth` is 0, otherwise a savepoint
+    /// within the `depth` levels which are open.
+    pub(crate) async fn begin(
+        &self,
+        depth: usize,
+        settings: TransactionSettings,
+    ) -> Result<(), Error> {
+        self.request(|tx| Command::Begin {
+            depth,
+            settings,
+            tx,
+        })
+        .await
+    }
+
+    /// Commits the transaction and turns autocommit back on if `depth` is 0, otherwise releases
+    /// the savepoint started at that depth.
+    pub(crate) async fn commit(&self, depth: usize) -> Result<(), Error> {
+        self.request(|tx| Command::Commit { depth, tx }).await
+    }
+
+    /// Rolls back the transaction and turns autocommit back on if `depth` is 0, otherwise rolls
+    /// back to the savepoint started at that depth.
+    pub(crate) async fn rollback(&self, depth: usize) -> Result<(), Error> {
+        self.request(|tx| Command::Rollback {
+            depth,
+            tx: Some(tx),
+        })
+        .await
+    }
+
+    /// Queues a rollback without waiting for it. Commands are processed in order, so it is
+    /// guaranteed to happen before anything sent afterwards.
+    pub(crate) fn start_rollback(&self, depth: usize) {
+        self.command_tx
+            .send(Command::Rollback { depth, tx: None })
+            .ok();
+    }
+
+    /// Rolls back an open transaction and disconnects, waiting for the outcome. Commands sent
+    /// before are processed first.
+    pub(crate) async fn close(self) -> Result<(), Error> {
+        self.request(|tx| Command::Close { tx }).await
+    }
+
+    /// Leaves the connection to the worker thread, which disconnects in the background. A
+    /// statement which is still executing is cancelled, so that this does not take long.
+    pub(crate) fn close_hard(self) {
+        if let Err(e) = self.running.cancel(None) {
+            log::warn!("failed to cancel statement: {e}");
+        }
+    }
+
+    /// Sends a command and waits for the worker to answer it.
+    async fn request<T>(
+        &self,
+        command: impl FnOnce(oneshot::Sender<Result<T, Error>>) -> Command,
+    ) -> Result<T, Error> {
+        let (tx, rx) = oneshot::channel();
+
+        self.command_tx
+            .send(command(tx))
+            .map_err(|_| Error::WorkerCrashed)?;
+
+        rx.await.map_err(|_| Error::WorkerCrashed)?
+    }
+}
+
+/// A connection handle owned by the worker. The handle is used directly rather than through
+/// `odbc_api::Connection`, which neither lets us pick the environment nor set the attributes it
+/// has no methods for.
+struct Connection {
+    // Freeing a handle which is still connected panics, so it is only dropped once disconnected.
+    handle: ManuallyDrop<handles::Connection<'static>>,
+    disconnected: bool,
+    /// Attributes changed for the current transaction, with the values to restore afterwards.
+    restore: RefCell<Vec<(ConnectionAttribute, u32)>>,
+    // Keeps the environment alive until the handle has been freed
+    _environment: ODBCEnvironment,
+}
+
+impl Connection {
+    fn establish(options: &ODBCConnectOptions) -> Result<Self, Error> {
+        let environment = match &options.environment {
+            Some(environment) => environment.clone(),
+            None => ODBCEnvironment::shared()?,
+        };
+        let mut handle = environment.allocate_connection()?;
+        if let Some(timeout) = options.login_timeout {
+            handle
+                .set_login_timeout_sec(timeout_secs(timeout))
+                .into_result(&handle)
+                .map_err(|e| odbc_error_from(e, &handle))?;
+        }
+        handle
+            .connect_with_connection_string(&SqlText::new(&options.connection_string))
+            .into_result(&handle)
+            .map_err(|e| odbc_error_from(e, &handle))?;
+        let conn = Connection {
+            handle: ManuallyDrop::new(handle),
+            disconnected: false,
+            restore: RefCell::new(Vec::new()),
+            _environment: environment,
+        };
+        if let Some(timeout) = options.connection_timeout {
+            conn.set_attribute(
+                ConnectionAttribute::ConnectionTimeout,
+                timeout_secs(timeout),
+            )?;
+        }
+        for (attribute, value) in options.transaction_settings.attributes() {
+            conn.set_attribute(attribute, value)?;
+        }
+        Ok(conn)
+    }
+
+    fn attribute(&self, attribute: ConnectionAttribute) -> Result<u32, Error> {
+        let mut value: u32 = 0;
+        let ret = unsafe {
+            SQLGetConnectAttrW(
+                self.handle.as_sys(),
+                attribute,
+                &mut value as *mut u32 as Pointer,
+                0,
+                ptr::null_mut(),
+            )
+        };
+        check(ret, "SQLGetConnectAttr", &*self.handle)?;
+        Ok(value)
+    }
+
+    /// Sets a connection attribute with an integer value.
+    fn set_attribute(&self, attribute: ConnectionAttribute, value: u32) -> Result<(), Error> {
+        let ret = unsafe {
+            SQLSetConnectAttrW(
+                self.handle.as_sys(),
+                attribute,
+                value as usize as Pointer,
+                0,
+            )
+        };
+        check(ret, "SQLSetConnectAttr", &*self.handle)
+    }
+
+    fn result<T>(&self, res: SqlResult<T>) -> Result<T, Error> {
+        res.into_result(&*self.handle)
+            .map_err(|e| odbc_error_from(e, &*self.handle))
+    }
+
+    fn allocate_statement(&self) -> Result<StatementImpl<'_>, Error> {
+        self.result(self.handle.allocate_statement())
+    }
+
+    /// Fails with an I/O error if the driver reports that the connection has been lost. Drivers
+    /// which cannot tell are assumed to be connected.
+    fn check_alive(&self) -> Result<(), Error> {
+        match self.handle.is_dead().into_result(&*self.handle) {
+            Ok(true) => Err(Error::Io(io::Error::new(
+                io::ErrorKind::ConnectionAborted,
+                "the connection to the data source has been lost",
+            ))),
+            _ => Ok(()),
+        }
+    }
+
+    fn ping(&self, validation_query: &str, query_timeout: u32) -> Result<(), Error> {
+        self.check_alive()?;
+        let res = self.allocate_statement().and_then(|mut stmt| {
+            set_query_timeout(&mut stmt, &mut 0, query_timeout)?;
+            execute_statement(&mut stmt, Some(validation_query), &ODBCArguments::default())
+        });
+        if res.is_err() {
+            // The query may be what made the driver notice a lost connection
+            self.check_alive()?;
+        }
+        res
+    }
+
+    /// Executes a statement without arguments or results, e.g. for savepoints.
+    fn execute_sql(&self, sql: &str) -> Result<(), Error> {
+        let mut stmt = self.allocate_statement()?;
+        execute_statement(&mut stmt, Some(sql), &ODBCArguments::default())
+    }
+
+    fn dbms_name(&self) -> Result<String, Error> {
+        let mut buf = Vec::with_capacity(64);
+        self.result(self.handle.fetch_database_management_system_name(&mut buf))?;
+        Ok(slice_to_cow_utf8(&buf).into_owned())
+    }
+
+    /// Rolls back an open transaction and disconnects. Statements must have been freed before.
+    /// Unlike dropping, this reports what goes wrong.
+    fn close(mut self) -> Result<(), Error> {
+        // Does nothing in autocommit mode
+        let rollback = self.result(self.handle.rollback());
+        let disconnect = self.handle.disconnect();
+        let disconnect = self.result(disconnect);
+        self.disconnected = disconnect.is_ok();
+        rollback.and(disconnect)
+    }
+
+    fn set_autocommit(&self, enabled: bool) -> Result<(), Error> {
+        self.result(self.handle.set_autocommit(enabled))
+    }
+
+    /// Starts a transaction by turning off autocommit. The attributes of `settings` are set
+    /// before, as drivers do not allow changing them while a transaction is open.
+    fn begin(&self, settings: TransactionSettings) -> Result<(), Error> {
+        let res = settings
+            .attributes()
+            .try_for_each(|(attribute, value)| {
+                let previous = self.attribute(attribute)?;
+                if previous != value {
+                    self.set_attribute(attribute, value)?;
+                    self.restore.borrow_mut().push((attribute, previous));
+                }
+                Ok(())
+            })
+            .and_then(|()| self.set_autocommit(false));
+        if res.is_err() {
+            self.restore_attributes().ok();
+        }
+        res
+    }
+
+    /// Ends the transaction with SQLEndTran. Autocommit is only turned back on if that
+    /// succeeded, as doing so would commit a transaction which is still open.
+    fn end_transaction(&self, commit: bool) -> Result<(), Error> {
+        self.result(if commit {
+            self.handle.commit()
+        } else {
+            self.handle.rollback()
+        })?;
+        self.set_autocommit(true)?;
+        self.restore_attributes()
+    }
+
+    /// Sets the attributes changed for a transaction back to their previous values, the most
+    /// recent change first, so the value saved first is the one that remains.
+    fn restore_attributes(&self) -> Result<(), Error> {
+        let mut res = Ok(());
+        for (attribute, value) in self.restore.take().into_iter().rev() {
+            res = res.and(self.set_attribute(attribute, value));
+        }
+        res
+    }
+}
+
+impl Drop for Connection {
+    fn drop(&mut self) {
+        if !self.disconnected {
+            let mut res = self.handle.disconnect().into_result(&*self.handle);
+            if res.is_err() {
+                // Disconnecting fails while a transaction is open, which is discarded then
+                self.handle.rollback().into_result(&*self.handle).ok();
+                res = self.handle.disconnect().into_result(&*self.handle);
+            }
+            if let Err(e) = res {
+                log::error!("failed to disconnect, leaking the connection handle: {e}");
+                return;
+            }
+        }
+        // Safe: the handle is not used afterwards
+        unsafe { ManuallyDrop::drop(&mut self.handle) }
+    }
+}
+
+/// The cheapest query the DBMS accepts, for checking that the connection works. `dbms` is its
+/// name in lowercase.
+fn default_validation_query(dbms: &str) -> &'static str {
+    if dbms.contains("oracle") {
+        "SELECT 1 FROM DUAL"
+    } else if dbms.starts_with("db2") {
+        "SELECT 1 FROM SYSIBM.SYSDUMMY1"
+    } else if dbms.contains("informix") {
+        "SELECT 1 FROM systables WHERE tabid = 1"
+    } else if dbms.contains("firebird") || dbms.contains("interbase") {
+        "SELECT 1 FROM RDB$DATABASE"
+    } else if dbms.contains("derby") || dbms.starts_with("hsql") {
+        "VALUES 1"
+    } else {
+        "SELECT 1"
+    }
+}
+
+/// How the DBMS spells savepoint statements. ODBC has no function for them.
+#[derive(Debug, Clone, Copy)]
+enum SavepointDialect {
+    /// `SAVEPOINT`, `ROLLBACK TO SAVEPOINT` and `RELEASE SAVEPOINT`
+    Standard,
+    /// Like `Standard`, but savepoints cannot be released
+    NoRelease,
+    /// `SAVE TRANSACTION` and `ROLLBACK TRANSACTION`, which cannot be released either
+    Transact,
+}
+
+impl SavepointDialect {
+    /// Picks the dialect from the DBMS name in lowercase.
+    fn of(dbms: &str) -> Self {
+        if dbms.contains("sql server") || dbms.contains("adaptive server") || dbms == "sybase" {
+            Self::Transact
+        } else if dbms.contains("oracle") {
+            Self::NoRelease
+        } else {
+            Self::Standard
+        }
+    }
+
+    fn begin(self, depth: usize) -> String {
+        match self {
+            Self::Standard | Self::NoRelease => format!("SAVEPOINT sqlx_savepoint_{depth}"),
+            Self::Transact => format!("SAVE TRANSACTION sqlx_savepoint_{depth}"),
+        }
+    }
+
+    /// Returns `None` if the savepoint is simply left open, as it goes away with the transaction.
+    fn release(self, depth: usize) -> Option<String> {
+        match self {
+            Self::Standard => Some(format!("RELEASE SAVEPOINT sqlx_savepoint_{depth}")),
+            Self::NoRelease | Self::Transact => None,
+        }
+    }
+
+    fn rollback(self, depth: usize) -> String {
+        match self {
+            Self::Standard | Self::NoRelease => {
+                format!("ROLLBACK TO SAVEPOINT sqlx_savepoint_{depth}")
+            }
+            Self::Transact => format!("ROLLBACK TRANSACTION sqlx_savepoint_{depth}"),
+        }
+    }
+}
+
+/// Converts a timeout to the whole seconds ODBC expects, rounding up. `0` means no timeout.
+fn timeout_secs(timeout: Duration) -> u32 {
+    let secs = timeout.as_secs() + u64::from(timeout.subsec_nanos() > 0);
+    secs.try_into().unwrap_or(u32::MAX)
+}
+
+/// Sets the query timeout of a statement whose timeout is `*current` seconds. The attribute is
+/// only touched if the value changes, as not every driver supports it.
+fn set_query_timeout(
+    stmt: &mut StatementImpl<'_>,
+    current: &mut u32,
+    timeout: u32,
+) -> Result<(), Error> {
+    if *current != timeout {
+        let ret = unsafe {
+            SQLSetStmtAttrW(
+                stmt.as_sys(),
+                StatementAttribute::QueryTimeout,
+                timeout as usize as Pointer,
+                0,
+            )
+        };
+        check(ret, "SQLSetStmtAttr", stmt)?;
+        *current = timeout;
+    }
+    Ok(())
+}
+
+/// Binds the arguments and executes `sql`, or the statement prepared on `stmt` if it is `None`.
+fn execute_statement(
+    stmt: &mut StatementImpl<'_>,
+    sql: Option<&str>,
+    arguments: &ODBCArguments,
+) -> Result<(), Error> {
+    unsafe fn bind_and_execute(
+        stmt: &mut StatementImpl<'_>,
+        sql: Option<&str>,
+        mut arguments: &ODBCArguments,
+    ) -> Result<(), odbc_api::Error> {
+        stmt.reset_parameters().into_result(stmt)?;
+        arguments.bind_parameters_to(stmt)?;
+        match sql {
+            Some(sql) => stmt.exec_direct(&SqlText::new(sql)),
+            None => stmt.execute(),
+        }
+        // Statements which do not affect any rows report SQL_NO_DATA
+        .into_result_with(stmt, Some(()), None)
+    }
+
+    // Safe: the arguments outlive the execution
+    unsafe { bind_and_execute(stmt, sql, arguments) }.map_err(|e| odbc_error_from(e, stmt))
+}
+
+fn execute(
+    conn: &Connection,
+    sql: &str,
+    arguments: &ODBCArguments,
+    ctx: &ExecutionContext<'_>,
+) -> Result<(), Error> {
+    let mut stmt = conn.allocate_statement()?;
+    set_query_timeout(&mut stmt, &mut 0, ctx.query_timeout)?;
+    let _running = ctx.running.start(ctx.execution, &stmt);
+    let call = ctx.running.driver_call();
+    let res = execute_statement(&mut stmt, Some(sql), arguments);
+    drop(call);
+    res?;
+    fetch_results(&mut stmt, ctx)
+}
+
+/// Executes a statement from the cache, preparing it first if it is not cached yet.
+fn execute_prepared<'c>(
+    conn: &'c Connection,
+    statements: &mut StatementCache<'c>,
+    sql: &str,
+    arguments: &ODBCArguments,
+    ctx: &ExecutionContext<'_>,
+) -> Result<(), Error> {
+    let stmt = cached(conn, statements, sql)?;
+    set_query_timeout(&mut stmt.handle, &mut stmt.query_timeout, ctx.query_timeout)?;
+    let _running = ctx.running.start(ctx.execution, &stmt.handle);
+    let call = ctx.running.driver_call();
+    let res = execute_statement(&mut stmt.handle, None, arguments);
+    drop(call);
+    res?;
+    fetch_results(&mut stmt.handle, ctx)
+}
+
+fn prepare_statement<'c>(conn: &'c Connection, sql: &str) -> Result<StatementImpl<'c>, Error> {
+    let mut stmt = conn.allocate_statement()?;
+    stmt.prepare(&SqlText::new(sql))
+        .into_result(&stmt)
+        .map_err(|e| odbc_error_from(e, &stmt))?;
+    Ok(stmt)
+}
+
+/// Looks up the prepared statement for `sql`, preparing and caching it if necessary.
+fn cached<'a, 'c>(
+    conn: &'c Connection,
+    statements: &'a mut StatementCache<'c>,
+    sql: &str,
+) -> Result<&'a mut PreparedStatement<'c>, Error> {
+    if !statements.contains_key(sql) {
+        let stmt = PreparedStatement {
+            handle: prepare_statement(conn, sql)?,
+            query_timeout: 0,
+        };
+        // The least recently used statement is dropped, which frees its handle.
+        statements.insert(sql, stmt);
+    }
+    Ok(statements
+        .get_mut(sql)
+        .expect("statement has just been inserted"))
+}
+
+/// Walks all results of an executed statement. The rows of each result set are followed by an
+/// `ODBCQueryResult`, as is every statement which does not produce rows.
+fn fetch_results(mut stmt: impl AsStatementRef, ctx: &ExecutionContext<'_>) -> Result<(), Error> {
+    loop {
+        let stmt_ref = stmt.as_stmt_ref();
+        let num_cols = stmt_ref
+            .num_result_cols()
+            .into_result(&stmt_ref)
+            .map_err(|e| odbc_error_from(e, &stmt_ref))?;
+        let rows_affected = if num_cols > 0 {
+            // Safe: the statement has a result set, so it is in cursor state
+            let cursor = unsafe { CursorImpl::new(stmt_ref) };
+            if !fetch(cursor, ctx)? {
+                return Ok(());
+            }
+            0
+        } else {
+            let count = stmt_ref
+                .row_count()
+                .into_result(&stmt_ref)
+                .map_err(|e| odbc_error_from(e, &stmt_ref))?;
+            // Drivers report -1 if the number of rows is not available
+            count.max(0) as u64
+        };
+
+        let mut stmt_ref = stmt.as_stmt_ref();
+        if ctx
+            .tx
+            .send(Ok(Either::Left(ODBCQueryResult { rows_affected })))
+            .is_err()
+        {
+            // Discard the remaining results, so the statement can be executed again
+            close_cursor(stmt_ref);
+            return Ok(());
+        }
+        let call = ctx.running.driver_call();
+        let more = unsafe { stmt_ref.more_results() };
+        drop(call);
+        let more = more
+            .into_result_bool(&stmt_ref)
+            .map_err(|e| odbc_error_from(e, &stmt_ref))?;
+        if !more {
+            return Ok(());
+        }
+    }
+}
+
+/// Closes the cursor of `stmt` and discards pending results. Unlike SQLCloseCursor, which
+/// odbc-api's cursors call when dropped and panic if it fails, SQLFreeStmt with SQL_CLOSE does not
+/// fail if the driver closed the cursor already, e.g. after SQLCancel.
+fn close_cursor(mut stmt: impl AsStatementRef) {
+    let stmt = stmt.as_stmt_ref();
+    let ret = unsafe { SQLFreeStmt(stmt.as_sys(), FreeStmtOption::Close) };
+    if let Err(e) = check(ret, "SQLFreeStmt", &stmt) {
+        log::warn!("failed to close cursor: {e}");
+    }
+}
+
+/// Fetches the rows of the current result set. Returns `false` if the stream has been dropped, in
+/// which case the cursor is closed, as it is if fetching fails. Otherwise the statement is left
+/// on the result set, so SQLMoreResults can move on to the next one.
+fn fetch(
+    mut cursor: CursorImpl<StatementRef<'_>>,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, Error> {
+    let columns = match describe_columns(&mut cursor) {
+        Ok(columns) => ResultColumns::new(columns),
+        Err(e) => return finish_fetch(cursor, Err(e)),
+    };
+    match columns
+        .columns
+        .iter()
+        .map(|c| buffer_desc(c.type_info.0))
+        .collect::<Option<Vec<_>>>()
+    {
+        Some(descs) => fetch_blocks(cursor, descs, columns, ctx),
+        None => fetch_rows(cursor, columns, ctx),
+    }
+}
+
+/// Leaves the statement on the result set if all of its rows have been fetched, and closes the
+/// cursor otherwise.
+fn finish_fetch(
+    cursor: CursorImpl<StatementRef<'_>>,
+    res: Result<bool, Error>,
+) -> Result<bool, Error> {
+    let stmt = cursor.into_stmt();
+    if !matches!(res, Ok(true)) {
+        close_cursor(stmt);
+    }
+    res
+}
+
+/// Column metadata of a result set, shared by all of its rows.
+struct ResultColumns {
+    columns: Arc<Vec<ODBCColumn>>,
+    column_names: Arc<HashMap<UStr, usize>>,
+}
+
+impl ResultColumns {
+    fn new(columns: Vec<ODBCColumn>) -> Self {
+        let column_names = columns
+            .iter()
+            .map(|c| (UStr::new(&c.name), c.ordinal))
+            .collect();
+        ResultColumns {
+            columns: Arc::new(columns),
+            column_names: Arc::new(column_names),
+        }
+    }
+
+    fn row(&self, values: Vec<ODBCValueOpt>) -> ODBCRow {
+        ODBCRow {
+            values,
+            columns: Arc::clone(&self.columns),
+            column_names: Arc::clone(&self.column_names),
+        }
+    }
+}
+
+/// Describes the buffer a column is bound to for block fetching. `None` if values of the column
+/// may be too large to be bound.
+fn buffer_desc(data_type: DataType) -> Option<BufferDesc> {
+    let desc = match data_type {
+        // TINYINT is unsigned for some DBMS, a 16 bit integer fits either way
+        DataType::TinyInt | DataType::SmallInt => BufferDesc::I16 { nullable: true },
+        DataType::Integer => BufferDesc::I32 { nullable: true },
+        DataType::BigInt => BufferDesc::I64 { nullable: true },
+        DataType::Bit => BufferDesc::Bit { nullable: true },
+        DataType::Real => BufferDesc::F32 { nullable: true },
+        DataType::Double | DataType::Float { precision: _ } => BufferDesc::F64 { nullable: true },
+        DataType::Char { length }
+        | DataType::LongVarchar { length }
+        | DataType::Varchar { length }
+        | DataType::WChar { length }
+        | DataType::WVarchar { length } => BufferDesc::Text {
+            // A character may take up to four bytes in UTF-8
+            max_str_len: length * 4,
+        },
+        DataType::Binary { length }
+        | DataType::Varbinary { length }
+        | DataType::LongVarbinary { length } => BufferDesc::Binary { length },
+        DataType::Date => BufferDesc::Date { nullable: true },
+        DataType::Time { precision: 0 } => BufferDesc::Time { nullable: true },
+        // SQL_TIME_STRUCT has no fractional seconds, so these are fetched as text
+        DataType::Time { precision } => BufferDesc::Text {
+            max_str_len: 9 + precision.max(0) as usize,
+        },
+        DataType::Timestamp { precision: _ } => BufferDesc::Timestamp { nullable: true },
+        // Drivers convert GUIDs to their canonical text
+        DataType::Other {
+            data_type: SqlDataType::EXT_GUID,
+            ..
+        } => BufferDesc::Text { max_str_len: 36 },
+        // Fetched as text, so no digits are lost. It has room for a sign, a leading zero and the
+        // decimal point, as in `-0.1234` for DECIMAL(4, 4).
+        DataType::Numeric { precision, .. } | DataType::Decimal { precision, .. } => {
+            BufferDesc::Text {
+                max_str_len: precision + 3,
+            }
+        }
+        _ => return None,
+    };
+    match desc {
+        // Drivers report 0 if they do not know an upper bound for the length
+        BufferDesc::Text { max_str_len: 0 } | BufferDesc::Binary { length: 0 } => None,
+        BufferDesc::Text { max_str_len: len } | BufferDesc::Binary { length: len }
+            if len > MAX_BOUND_VALUE_SIZE =>
+        {
+            None
+        }
+        desc => Some(desc),
+    }
+}
+
+/// Fetches the result set in blocks of `batch_size` rows through bound column buffers.
+fn fetch_blocks(
+    cursor: CursorImpl<StatementRef<'_>>,
+    descs: Vec<BufferDesc>,
+    columns: ResultColumns,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, Error> {
+    let buffer = ColumnarAnyBuffer::try_from_descs(ctx.batch_size, descs).map_err(odbc_error)?;
+    let mut cursor = cursor.bind_buffer(buffer).map_err(odbc_error)?;
+    let res = send_blocks(&mut cursor, &columns, ctx);
+    let (cursor, _) = cursor.unbind().map_err(odbc_error)?;
+    finish_fetch(cursor, res)
+}
+
+/// Sends the rows of each fetched block. Returns `false` if the stream has been dropped.
+fn send_blocks(
+    cursor: &mut BlockCursor<CursorImpl<StatementRef<'_>>, ColumnarAnyBuffer>,
+    columns: &ResultColumns,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, Error> {
+    loop {
+        let call = ctx.running.driver_call();
+        let batch = cursor.fetch_with_truncation_check(true);
+        drop(call);
+        let batch = match batch.map_err(odbc_error)? {
+            Some(batch) => batch,
+            None => return Ok(true),
+        };
+        let mut values: Vec<_> = (0..batch.num_rows())
+            .map(|_| Vec::with_capacity(columns.columns.len()))
+            .collect();
+        for column in columns.columns.iter() {
+            let column_values = read_column(batch.column(column.ordinal), column);
+            for (row, value) in values.iter_mut().zip(column_values) {
+                row.push(value);
+            }
+        }
+        for values in values {
+            if ctx.tx.send(Ok(Either::Right(columns.row(values)))).is_err() {
+                // The stream has been dropped, nobody is interested in the remaining rows.
+                return Ok(false);
+            }
+        }
+    }
+}
+
+/// Copies the values of one column of a fetched block.
+fn read_column(slice: AnySlice<'_>, column: &ODBCColumn) -> Vec<ODBCValueOpt> {
+    let value = |v: Option<ODBCValue>| match v {
+        None => ODBCValueOpt::Null(column.type_info),
+        Some(v) => ODBCValueOpt::Value(v),
+    };
+    match slice {
+        AnySlice::NullableBit(it) => it.map(|v| value(v.copied().map(ODBCValue::Bit))).collect(),
+        AnySlice::NullableI16(it) => it
+            .map(|v| value(v.copied().map(ODBCValue::SmallInt)))
+            .collect(),
+        AnySlice::NullableI32(it) => it.map(|v| value(v.copied().map(ODBCValue::Int))).collect(),
+        AnySlice::NullableI64(it) => it
+            .map(|v| value(v.copied().map(ODBCValue::Int64)))
+            .collect(),
+        AnySlice::NullableF32(it) => it.map(|v| value(v.copied().map(ODBCValue::Real))).collect(),
+        AnySlice::NullableF64(it) => it
+            .map(|v| value(v.copied().map(ODBCValue::Double)))
+            .collect(),
+        AnySlice::Text(view) => view
+            .iter()
+            .map(|v| value(v.map(|b| text_value(b.to_vec(), column))))
+            .collect(),
+        AnySlice::Binary(view) => view
+            .iter()
+            .map(|v| value(v.map(|b| ODBCValue::Binary(VarBinaryBox::from_vec(b.to_vec())))))
+            .collect(),
+        AnySlice::NullableDate(it) => it.map(|v| value(v.copied().map(ODBCValue::Date))).collect(),
+        AnySlice::NullableTime(it) => it.map(|v| value(v.copied().map(ODBCValue::Time))).collect(),
+        AnySlice::NullableTimestamp(it) => it
+            .map(|v| value(v.map(|t| fetched_timestamp(*t, column.type_info.0))))
+            .collect(),
+        _ => unreachable!("column buffers are created by `buffer_desc`"),
+    }
+}
+
+/// Fetches the result set one row at a time, retrieving each value separately.
+fn fetch_rows(
+    mut cursor: CursorImpl<StatementRef<'_>>,
+    columns: ResultColumns,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, Error> {
+    let res = send_rows(&mut cursor, &columns, ctx);
+    finish_fetch(cursor, res)
+}
+
+/// Sends each fetched row. Returns `false` if the stream has been dropped.
+fn send_rows(
+    cursor: &mut CursorImpl<StatementRef<'_>>,
+    columns: &ResultColumns,
+    ctx: &ExecutionContext<'_>,
+) -> Result<bool, Error> {
+    loop {
+        let call = ctx.running.driver_call();
+        let row = cursor.next_row();
+        drop(call);
+        let mut row = match row.map_err(odbc_error)? {
+            Some(row) => row,
+            None => return Ok(true),
+        };
+        let values = read_row(&mut row, &columns.columns)?;
+        if ctx.tx.send(Ok(Either::Right(columns.row(values)))).is_err() {
+            // The stream has been dropped, nobody is interested in the remaining rows.
+            return Ok(false);
+        }
+    }
+}
+
+fn describe_columns(stmt: &mut impl AsStatementRef) -> Result<Vec<ODBCColumn>, Error> {
+    let stmt = stmt.as_stmt_ref();
+    let num_cols = stmt
+        .num_result_cols()
+        .into_result(&stmt)
+        .map_err(|e| odbc_error_from(e, &stmt))?;
+    let mut colums: Vec<ODBCColumn> = Vec::with_capacity(num_cols.max(0) as usize);
+    for col in 1..=num_cols.max(0) as u16 {
+        let mut col_desc: ColumnDescription = Default::default();
+        stmt.describe_col(col, &mut col_desc)
+            .into_result(&stmt)
+            .map_err(|e| odbc_error_from(e, &stmt))?;
+        let name = col_desc.name_to_string().map_err(|e| {
+            Error::Protocol(format!("name of column {col} is not valid unicode: {e}"))
+        })?;
+        colums.push(ODBCColumn {
+            ordinal: (col - 1).into(),
+            name,
+            type_info: ODBCTypeInfo(col_desc.data_type),
+            nullability: col_desc.nullability,
+        })
+    }
+    Ok(colums)
+}
+
+/// Prepares `sql` and describes the resulting statement. The prepared statement is kept in the
+/// cache, so executing it afterwards does not prepare it again.
+fn prepare<'c>(
+    conn: &'c Connection,
+    statements: &mut StatementCache<'c>,
+    sql: &str,
+) -> Result<ODBCStatement<'static>, Error> {
+    let mut uncached;
+    let stmt = if statements.is_enabled() {
+        &mut cached(conn, statements, sql)?.handle
+    } else {
+        uncached = prepare_statement(conn, sql)?;
+        &mut uncached
+    };
+
+    let columns = ResultColumns::new(describe_columns(stmt)?);
+
+    let num_params = stmt
+        .num_params()
+        .into_result(stmt)
+        .map_err(|e| odbc_error_from(e, stmt))?;
+    let parameters = parameter_types(num_params, |i| {
+        stmt.describe_param(i)
+            .into_result(stmt)
+            .map(|param| param.data_type)
+    });
+
+    Ok(ODBCStatement {
+        sql: Cow::Owned(sql.to_owned()),
+        parameters,
+        columns: columns.columns,
+        column_names: columns.column_names,
+    })
+}
+
+/// The types of the parameters of a prepared statement. Not every driver supports SQLDescribeParam,
+/// so if describing one fails only their number is known.
+fn parameter_types<E>(
+    num_params: u16,
+    mut describe_param: impl FnMut(u16) -> Result<DataType, E>,
+) -> Either<Vec<ODBCTypeInfo>, usize> {
+    match (1..=num_params)
+        .map(|i| describe_param(i).map(ODBCTypeInfo))
+        .collect()
+    {
+        Ok(parameters) => Either::Left(parameters),
+        Err(_) => Either::Right(num_params.into()),
+    }
+}
+
+fn describe<'c>(
+    conn: &'c Connection,
+    statements: &mut StatementCache<'c>,
+    sql: &str,
+) -> Result<Describe<ODBC>, Error> {
+    let stmt = prepare(conn, statements, sql)?;
+
+    let nullable = stmt
+        .columns
+        .iter()
+        .map(|c| match c.nullability {
+            Nullability::NoNulls => Some(false),
+            Nullability::Nullable => Some(true),
+            Nullability::Unknown => None,
+        })
+        .collect();
+
+    Ok(Describe {
+        columns: stmt.columns.to_vec(),
+        parameters: Some(stmt.parameters),
+        nullable,
+    })
+}
+
+/// Reads all values of the current row, so the row stays valid after the cursor moves on.
+fn read_row(row: &mut CursorRow<'_>, columns: &[ODBCColumn]) -> Result<Vec<ODBCValueOpt>, Error> {
+    let mut values = Vec::with_capacity(columns.len());
+    for column in columns {
+        let value = read_value(row, column).map_err(|source| Error::ColumnDecode {
+            index: format!("{:?}", column.ordinal),
+            source,
+        })?;
+        values.push(match value {
+            None => ODBCValueOpt::Null(column.type_info),
+            Some(v) => ODBCValueOpt::Value(v),
+        });
+    }
+    Ok(values)
+}
+
+fn read_value(
+    row: &mut CursorRow<'_>,
+    column: &ODBCColumn,
+) -> Result<Option<ODBCValue>, BoxDynError> {
+    fn get_value<T>(row: &mut CursorRow<'_>, col: u16) -> Result<Option<T>, odbc_api::Error>
+    where
+        T: Clone + Default,
+        Nullable<T>: odbc_api::parameter::CElement + odbc_api::handles::CDataMut,
+    {
+        let mut res: Nullable<T> = Nullable::null();
+        row.get_data(col, &mut res)?;
+        Ok(res.into_opt())
+    }
+
+    let col = u16::try_from(column.ordinal + 1)?;
+    let value = match column.type_info.0 {
+        DataType::TinyInt | DataType::SmallInt => {
+            get_value::<i16>(row, col)?.map(ODBCValue::SmallInt)
+        }
+        DataType::Integer => get_value::<i32>(row, col)?.map(ODBCValue::Int),
+        DataType::BigInt => get_value::<i64>(row, col)?.map(ODBCValue::Int64),
+        DataType::Bit => get_value::<Bit>(row, col)?.map(ODBCValue::Bit),
+        DataType::Real => get_value::<f32>(row, col)?.map(ODBCValue::Real),
+        DataType::Double | DataType::Float { precision: _ } => {
+            get_value::<f64>(row, col)?.map(ODBCValue::Double)
+        }
+        DataType::Char { length: _ }
+        | DataType::LongVarchar { length: _ }
+        | DataType::Varchar { length: _ }
+        | DataType::WChar { length: _ }
+        | DataType::WVarchar { length: _ } => {
+            let mut res = Vec::<u8>::new();
+            row.get_text(col, &mut res)?
+                .then(|| ODBCValue::String(VarCharBox::from_vec(res)))
+        }
+        DataType::Binary { length: _ }
+        | DataType::Varbinary { length: _ }
+        | DataType::LongVarbinary { length: _ } => {
+            let mut res = Vec::<u8>::new();
+            row.get_binary(col, &mut res)?
+                .then(|| ODBCValue::Binary(VarBinaryBox::from_vec(res)))
+        }
+        DataType::Date => get_value::<Date>(row, col)?.map(ODBCValue::Date),
+        DataType::Time { precision: 0 } => get_value::<Time>(row, col)?.map(ODBCValue::Time),
+        DataType::Time { precision: _ } => {
+            let mut res = Vec::<u8>::new();
+            row.get_text(col, &mut res)?
+                .then(|| ODBCValue::String(VarCharBox::from_vec(res)))
+        }
+        DataType::Timestamp { precision: _ } => {
+            get_value::<Timestamp>(row, col)?.map(|t| fetched_timestamp(t, column.type_info.0))
+        }
+        DataType::Numeric { .. }
+        | DataType::Decimal { .. }
+        | DataType::Other {
+            data_type: SqlDataType::EXT_GUID,
+            ..
+        } => {
+            let mut res = Vec::<u8>::new();
+            row.get_text(col, &mut res)?
+                .then(|| text_value(res, column))
+        }
+        // Types without a mapping of their own, like SQL Server's `datetimeoffset` or `xml`, are
+        // fetched as text, or as binary if the driver cannot convert them. Whether that suits
+        // the Rust type is up to `Decode`, once the column is read.
+        _ => {
+            let mut res = Vec::<u8>::new();
+            match row.get_text(col, &mut res) {
+                Ok(not_null) => not_null.then(|| ODBCValue::String(VarCharBox::from_vec(res))),
+                Err(_) => {
+                    res.clear();
+                    row.get_binary(col, &mut res)?
+                        .then(|| ODBCValue::Binary(VarBinaryBox::from_vec(res)))
+                }
+            }
+        }
+    };
+    Ok(value)
+}
+
+/// A fetched timestamp, with the fractional seconds cut off at the precision of the column. Some
+/// drivers fill in digits the DBMS does not store.
+fn fetched_timestamp(mut value: Timestamp, data_type: DataType) -> ODBCValue {
+    let precision = match data_type {
+        DataType::Timestamp { precision } => precision.clamp(0, 9),
+        _ => 9,
+    };
+    value.fraction -= value.fraction % 10u32.pow((9 - precision) as u32);
+    ODBCValue::Timestamp(value, precision)
+}
+
+/// A value fetched as text. Exact numbers keep the type of their column.
+fn text_value(text: Vec<u8>, column: &ODBCColumn) -> ODBCValue {
+    let text = VarCharBox::from_vec(text);
+    if column.type_info.is_exact_numeric() {
+        ODBCValue::Numeric(text, column.type_info.0)
+    } else {
+        ODBCValue::String(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameter_types_without_describe_param() {
+        let described = parameter_types(2, |_| Ok::<_, ()>(DataType::Integer));
+        assert_eq!(
+            Either::Left(vec![ODBCTypeInfo(DataType::Integer); 2]),
+            described
+        );
+
+        // Drivers without SQLDescribeParam fail with HYC00 or IM001.
+        let undescribed = parameter_types(2, |i| match i {
+            1 => Ok(DataType::Integer),
+            _ => Err(()),
+        });
+        assert_eq!(Either::Right(2), undescribed);
+    }
+}
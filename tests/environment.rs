@@ -0,0 +1,49 @@
+use std::fs;
+
+use sqlx_odbc::{ODBCDataSourceScope, ODBCEnvironment};
+
+// Points unixODBC at configuration files of its own. The variables are read whenever drivers or
+// data sources are listed, so they only need to be set before.
+#[tokio::test]
+async fn list_drivers_and_data_sources() {
+    let dir = std::env::temp_dir().join(format!("sqlx-odbc-test-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(
+        dir.join("odbcinst.ini"),
+        "[Test Driver]\nDescription=Driver for testing\nDriver=/nonexistent/libtest.so\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("odbc.ini"),
+        "[system_dsn]\nDriver=Test Driver\nDescription=System DSN\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("user.ini"),
+        "[user_dsn]\nDriver=Test Driver\nDescription=User DSN\n",
+    )
+    .unwrap();
+    std::env::set_var("ODBCSYSINI", &dir);
+    std::env::set_var("ODBCINI", dir.join("user.ini"));
+
+    let environment = ODBCEnvironment::new().unwrap();
+
+    let drivers = environment.drivers().await.unwrap();
+    let driver = drivers.iter().find(|d| d.name() == "Test Driver").unwrap();
+    assert_eq!(Some("/nonexistent/libtest.so"), driver.attribute("driver"));
+
+    let data_sources = environment.data_sources().await.unwrap();
+    let user_dsn = data_sources
+        .iter()
+        .find(|d| d.name() == "user_dsn")
+        .unwrap();
+    assert_eq!(ODBCDataSourceScope::User, user_dsn.scope());
+    assert_eq!("Test Driver", user_dsn.description());
+    let system_dsn = data_sources
+        .iter()
+        .find(|d| d.name() == "system_dsn")
+        .unwrap();
+    assert_eq!(ODBCDataSourceScope::System, system_dsn.scope());
+
+    fs::remove_dir_all(&dir).ok();
+}
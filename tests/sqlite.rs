@@ -1,13 +1,19 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Once};
+use std::time::Duration;
+
 use futures_util::StreamExt;
-use sqlx::{query, Column, ConnectOptions, Connection, Executor, Row};
-use sqlx_odbc::{ODBCConnectOptions, ODBCConnection};
+use log::LevelFilter;
+use sqlx::{query, Column, ConnectOptions, Connection, Either, Executor, Row, Statement};
+use sqlx_odbc::{
+    ODBCAccessMode, ODBCConnectOptions, ODBCConnection, ODBCEnvironmentOptions, ODBCIsolationLevel,
+    ODBCQueryOptions, ODBCVersion,
+};
 
 async fn test_connection() -> ODBCConnection {
-    let connect_options = ODBCConnectOptions {
-        // FIXME: This only works on macos right now
-        connection_string: "Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;"
-            .to_string(),
-    };
+    // FIXME: This only works on macos right now
+    let connect_options =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;");
     connect_options.connect().await.unwrap()
 }
 
@@ -16,6 +22,47 @@ async fn connect() {
     let _ = test_connection().await;
 }
 
+#[tokio::test]
+async fn ping() {
+    let mut conn = test_connection().await;
+    conn.ping().await.unwrap();
+
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .validation_query("select * from no_such_table")
+            .connect()
+            .await
+            .unwrap();
+    assert!(conn.ping().await.is_err());
+}
+
+#[tokio::test]
+async fn close() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    conn.close().await.unwrap();
+
+    let conn = test_connection().await;
+    conn.close_hard().await.unwrap();
+}
+
+#[tokio::test]
+async fn environment() {
+    let environment = ODBCEnvironmentOptions::new()
+        .version(ODBCVersion::V3)
+        .create()
+        .unwrap();
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .environment(environment)
+            .connect()
+            .await
+            .unwrap();
+    conn.ping().await.unwrap();
+}
+
 #[tokio::test]
 async fn simple_select() {
     let mut conn = test_connection().await;
@@ -107,6 +154,63 @@ async fn roundtrip_i64() {
     test_for_type(42 as i64).await
 }
 
+#[tokio::test]
+async fn roundtrip_bool() {
+    test_for_type(true).await
+}
+
+#[tokio::test]
+async fn roundtrip_i8() {
+    test_for_type(-42_i8).await
+}
+
+#[tokio::test]
+async fn roundtrip_i16() {
+    test_for_type(-4242_i16).await
+}
+
+#[tokio::test]
+async fn roundtrip_u8() {
+    test_for_type(200_u8).await
+}
+
+#[tokio::test]
+async fn roundtrip_u16() {
+    test_for_type(60000_u16).await
+}
+
+#[tokio::test]
+async fn roundtrip_u32() {
+    test_for_type(4_000_000_000_u32).await
+}
+
+#[tokio::test]
+async fn roundtrip_u64() {
+    test_for_type(42_u64).await
+}
+
+#[tokio::test]
+async fn roundtrip_f32() {
+    test_for_type(42.5_f32).await
+}
+
+#[tokio::test]
+async fn integer_range_checks() {
+    let mut conn = test_connection().await;
+    let row = conn
+        .fetch_one("SELECT 300 AS big, -1 AS negative, 1 AS one")
+        .await
+        .unwrap();
+    assert_eq!(300, row.get::<i16, _>("big"));
+    assert_eq!(300, row.get::<u64, _>("big"));
+    assert!(row.try_get::<i8, _>("big").is_err());
+    assert!(row.try_get::<u8, _>("big").is_err());
+    assert_eq!(-1, row.get::<i8, _>("negative"));
+    assert!(row.try_get::<u32, _>("negative").is_err());
+    assert!(row.try_get::<bool, _>("big").is_err());
+    assert!(row.get::<bool, _>("one"));
+}
+
 #[tokio::test]
 async fn roundtrip_f64() {
     test_for_type(42.12 as f64).await
@@ -125,6 +229,183 @@ async fn roundtrip_binary() {
     .await
 }
 
+#[tokio::test]
+async fn typed_nulls() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(a INTEGER, b DOUBLE, c VARCHAR(10), d BLOB)")
+        .await
+        .unwrap();
+    query("INSERT INTO test(a, b, c, d) VALUES (?, ?, ?, ?)")
+        .bind(None::<i64>)
+        .bind(None::<f64>)
+        .bind(None::<String>)
+        .bind(None::<Vec<u8>>)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let row = conn.fetch_one("SELECT a, b, c, d FROM test").await.unwrap();
+    assert_eq!(None, row.get::<Option<i64>, _>("a"));
+    assert_eq!(None, row.get::<Option<f64>, _>("b"));
+    assert_eq!(None, row.get::<Option<String>, _>("c"));
+    assert_eq!(None, row.get::<Option<Vec<u8>>, _>("d"));
+}
+
+#[cfg(feature = "chrono")]
+#[tokio::test]
+async fn roundtrip_chrono_naive_date() {
+    test_for_type(chrono::NaiveDate::from_ymd_opt(2023, 11, 5).unwrap()).await
+}
+
+#[cfg(feature = "chrono")]
+#[tokio::test]
+async fn roundtrip_chrono_naive_time() {
+    test_for_type(chrono::NaiveTime::from_hms_opt(13, 37, 42).unwrap()).await
+}
+
+#[cfg(feature = "chrono")]
+#[tokio::test]
+async fn roundtrip_chrono_naive_date_time() {
+    test_for_type(
+        chrono::NaiveDate::from_ymd_opt(2023, 11, 5)
+            .unwrap()
+            .and_hms_milli_opt(13, 37, 42, 123)
+            .unwrap(),
+    )
+    .await
+}
+
+#[cfg(feature = "chrono")]
+#[tokio::test]
+async fn roundtrip_chrono_date_time_utc() {
+    test_for_type(
+        chrono::NaiveDate::from_ymd_opt(2023, 11, 5)
+            .unwrap()
+            .and_hms_milli_opt(13, 37, 42, 123)
+            .unwrap()
+            .and_utc(),
+    )
+    .await
+}
+
+#[cfg(feature = "chrono")]
+#[tokio::test]
+async fn chrono_columns() {
+    use chrono::{NaiveDate, NaiveDateTime};
+
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(d DATE, ts TIMESTAMP)")
+        .await
+        .unwrap();
+    let date = NaiveDate::from_ymd_opt(1999, 12, 31).unwrap();
+    let timestamp = date.and_hms_milli_opt(23, 59, 59, 250).unwrap();
+    query("INSERT INTO test(d, ts) VALUES (?, ?)")
+        .bind(date)
+        .bind(timestamp)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let row = conn.fetch_one("SELECT d, ts FROM test").await.unwrap();
+    assert_eq!(date, row.get::<NaiveDate, _>("d"));
+    assert_eq!(timestamp, row.get::<NaiveDateTime, _>("ts"));
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn roundtrip_time_date() {
+    test_for_type(time::macros::date!(2023 - 11 - 05)).await
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn roundtrip_time_time() {
+    test_for_type(time::macros::time!(13:37:42)).await
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn roundtrip_time_primitive_date_time() {
+    test_for_type(time::macros::datetime!(2023-11-05 13:37:42.123)).await
+}
+
+#[cfg(feature = "time")]
+#[tokio::test]
+async fn roundtrip_time_offset_date_time() {
+    test_for_type(time::macros::datetime!(2023-11-05 13:37:42.123 UTC)).await
+}
+
+#[cfg(feature = "rust_decimal")]
+#[tokio::test]
+async fn roundtrip_rust_decimal() {
+    test_for_type("-1234.5678".parse::<rust_decimal::Decimal>().unwrap()).await
+}
+
+#[cfg(feature = "rust_decimal")]
+#[tokio::test]
+async fn decimal_scale_equals_precision() {
+    let value = "-0.1234".parse::<rust_decimal::Decimal>().unwrap();
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x DECIMAL(4, 4))")
+        .await
+        .unwrap();
+    query("INSERT INTO test(x) VALUES (?)")
+        .bind(value)
+        .execute(&mut conn)
+        .await
+        .unwrap();
+    let row = conn.fetch_one("SELECT x FROM test").await.unwrap();
+    assert_eq!(value, row.get::<rust_decimal::Decimal, _>("x"));
+}
+
+#[cfg(feature = "bigdecimal")]
+#[tokio::test]
+async fn roundtrip_bigdecimal() {
+    test_for_type("-1234.5678".parse::<bigdecimal::BigDecimal>().unwrap()).await
+}
+
+#[tokio::test]
+async fn decimal_integers() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x DECIMAL(20, 0), y DECIMAL(10, 1), z DECIMAL(20, 0))")
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO test(x, y, z) VALUES (42, 1.5, -1)")
+        .await
+        .unwrap();
+    let row = conn.fetch_one("SELECT x, y, z FROM test").await.unwrap();
+    assert_eq!(42, row.get::<i128, _>("x"));
+    assert_eq!(42, row.get::<u64, _>("x"));
+    assert!(row.try_get::<i128, _>("y").is_err());
+    assert_eq!(-1, row.get::<i128, _>("z"));
+    assert!(row.try_get::<u64, _>("z").is_err());
+
+    // SQL Server renders zero with a scale as `.00`. TEXT affinity keeps it that way in SQLite.
+    conn.execute("CREATE TABLE zero(x DECIMAL(10, 2) TEXT)")
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO zero(x) VALUES ('.00')")
+        .await
+        .unwrap();
+    let row = conn.fetch_one("SELECT x FROM zero").await.unwrap();
+    assert_eq!(0, row.get::<i128, _>("x"));
+    assert_eq!(0, row.get::<u64, _>("x"));
+}
+
+#[cfg(feature = "uuid")]
+#[tokio::test]
+async fn uuid_from_text_and_binary() {
+    let uuid = uuid::Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap();
+    let mut conn = test_connection().await;
+    let row = conn
+        .fetch_one(
+            "SELECT '67e55044-10b1-426f-9247-bb680e5fe0c8' AS t, \
+             X'67e5504410b1426f9247bb680e5fe0c8' AS b",
+        )
+        .await
+        .unwrap();
+    assert_eq!(uuid, row.get::<uuid::Uuid, _>("t"));
+    assert_eq!(uuid, row.get::<uuid::Uuid, _>("b"));
+}
+
 #[tokio::test]
 async fn describe() {
     let mut conn = test_connection().await;
@@ -138,6 +419,31 @@ async fn describe() {
     )
 }
 
+#[tokio::test]
+async fn prepare() {
+    let mut conn = test_connection().await;
+    let stmt = conn.prepare("select ?+1 as num").await.unwrap();
+    assert_eq!(
+        stmt.columns()
+            .iter()
+            .map(|c| c.name())
+            .collect::<Vec<&str>>(),
+        vec!("num")
+    );
+    assert!(matches!(stmt.parameters(), Some(Either::Left(params)) if params.len() == 1));
+
+    for i in 0..3 {
+        let val: i64 = stmt
+            .query()
+            .bind(i)
+            .fetch_one(&mut conn)
+            .await
+            .unwrap()
+            .get("num");
+        assert_eq!(i + 1, val);
+    }
+}
+
 #[tokio::test]
 async fn fetch_many() {
     let mut conn = test_connection().await;
@@ -176,6 +482,9 @@ async fn fetch_many() {
     let v2: String = res2.get(1);
     assert_eq!((2, "two".to_owned()), (v1, v2));
 
+    let (res_done, tail) = tail.into_future().await;
+    assert!(res_done.unwrap().unwrap().is_left());
+
     let (res_empty, _tail) = tail.into_future().await;
     match res_empty {
         None => {}
@@ -183,12 +492,35 @@ async fn fetch_many() {
     }
 }
 
+#[tokio::test]
+async fn rows_affected() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    let res = conn
+        .execute("INSERT INTO test(x) VALUES (1), (2), (3)")
+        .await
+        .unwrap();
+    assert_eq!(3, res.rows_affected());
+    let res = conn
+        .execute("UPDATE test SET x = x + 1 WHERE x > 1")
+        .await
+        .unwrap();
+    assert_eq!(2, res.rows_affected());
+}
+
 #[tokio::test]
 async fn transaction_rollback() {
     let mut conn = test_connection().await;
-    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)");
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
     let mut transaction = conn.begin().await.unwrap();
-    transaction.execute("INSERT INTO test(x) VALUES (42)");
+    transaction
+        .execute("INSERT INTO test(x) VALUES (42)")
+        .await
+        .unwrap();
     let res = transaction.fetch_one("SELECT * FROM test").await.unwrap();
     assert_eq!(res.get::<i64, usize>(0), 42);
     transaction.rollback().await.unwrap();
@@ -202,10 +534,460 @@ async fn transaction_rollback() {
 #[tokio::test]
 async fn transaction_commit() {
     let mut conn = test_connection().await;
-    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)");
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    let mut transaction = conn.begin().await.unwrap();
+    transaction
+        .execute("INSERT INTO test(x) VALUES (42)")
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    let res = conn.fetch_one("SELECT * from test").await.unwrap();
+    assert_eq!(res.get::<i64, usize>(0), 42);
+}
+
+#[tokio::test]
+async fn transaction_dropped() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    {
+        let mut transaction = conn.begin().await.unwrap();
+        transaction
+            .execute("INSERT INTO test(x) VALUES (42)")
+            .await
+            .unwrap();
+    }
+    let res = conn.fetch_optional("SELECT * from test").await.unwrap();
+    match res {
+        None => {}
+        Some(_) => panic!("Found result where there should be none"),
+    }
+}
+
+#[tokio::test]
+async fn transaction_savepoints() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
     let mut transaction = conn.begin().await.unwrap();
-    transaction.execute("INSERT INTO test(x) VALUES (42)");
+    transaction
+        .execute("INSERT INTO test(x) VALUES (1)")
+        .await
+        .unwrap();
+    {
+        let mut savepoint = transaction.begin().await.unwrap();
+        savepoint
+            .execute("INSERT INTO test(x) VALUES (2)")
+            .await
+            .unwrap();
+        savepoint.rollback().await.unwrap();
+    }
+    {
+        let mut savepoint = transaction.begin().await.unwrap();
+        savepoint
+            .execute("INSERT INTO test(x) VALUES (3)")
+            .await
+            .unwrap();
+        savepoint.commit().await.unwrap();
+    }
+    {
+        let mut savepoint = transaction.begin().await.unwrap();
+        savepoint
+            .execute("INSERT INTO test(x) VALUES (4)")
+            .await
+            .unwrap();
+        // Dropped, which only rolls back the savepoint
+    }
+    transaction.commit().await.unwrap();
+    let values: Vec<i64> = conn
+        .fetch_all("SELECT x FROM test ORDER BY x")
+        .await
+        .unwrap()
+        .iter()
+        .map(|row| row.get(0))
+        .collect();
+    assert_eq!(vec![1, 3], values);
+}
+
+#[tokio::test]
+async fn transaction_begin_with() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    let mut transaction = conn
+        .begin_with(ODBCIsolationLevel::Serializable, ODBCAccessMode::ReadWrite)
+        .await
+        .unwrap();
+    transaction
+        .execute("INSERT INTO test(x) VALUES (42)")
+        .await
+        .unwrap();
     transaction.commit().await.unwrap();
     let res = conn.fetch_one("SELECT * from test").await.unwrap();
     assert_eq!(res.get::<i64, usize>(0), 42);
 }
+
+#[tokio::test]
+async fn transaction_settings_apply_once() {
+    let mut conn = test_connection().await;
+    let mut transaction = conn.begin().await.unwrap();
+    // The settings reach the nested begin, which rejects them for a savepoint
+    let err = transaction
+        .begin_with(ODBCIsolationLevel::Serializable, ODBCAccessMode::ReadOnly)
+        .await
+        .err();
+    assert!(matches!(err, Some(sqlx::Error::Configuration(_))));
+    // Neither the failed begin_with nor one that is never polled leaves them for the next begin
+    drop(transaction.begin_with(ODBCIsolationLevel::Serializable, ODBCAccessMode::ReadOnly));
+    let nested = transaction.begin().await.unwrap();
+    nested.commit().await.unwrap();
+    transaction.commit().await.unwrap();
+}
+
+#[tokio::test]
+async fn connection_transaction_settings() {
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .isolation_level(ODBCIsolationLevel::Serializable)
+            .access_mode(ODBCAccessMode::ReadWrite)
+            .connect()
+            .await
+            .unwrap();
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    let mut transaction = conn.begin().await.unwrap();
+    transaction
+        .execute("INSERT INTO test(x) VALUES (42)")
+        .await
+        .unwrap();
+    transaction.commit().await.unwrap();
+    let res = conn.fetch_one("SELECT * from test").await.unwrap();
+    assert_eq!(res.get::<i64, usize>(0), 42);
+}
+
+#[tokio::test(flavor = "current_thread")]
+async fn concurrent_queries() {
+    let mut conn1 = test_connection().await;
+    let mut conn2 = test_connection().await;
+    let (res1, res2) = futures_util::join!(
+        query("select 1 as test_column").fetch_one(&mut conn1),
+        query("select 2 as test_column").fetch_one(&mut conn2)
+    );
+    assert_eq!(res1.unwrap().get::<i64, usize>(0), 1);
+    assert_eq!(res2.unwrap().get::<i64, usize>(0), 2);
+}
+
+#[tokio::test]
+async fn spawned_connection() {
+    let mut conn = test_connection().await;
+    let res = tokio::spawn(async move {
+        let res = query("select 42 as test_column")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap();
+        res.get::<i64, usize>(0)
+    })
+    .await
+    .unwrap();
+    assert_eq!(42, res)
+}
+
+#[tokio::test]
+async fn fetch_in_batches() {
+    let mut conn = test_connection().await;
+    let rows = query(
+        "with recursive cte(x) as
+            (select 1 union all select x + 1 from cte where x < 1000)
+            select x, 'row ' || x from cte",
+    )
+    .fetch_all(conn.with_options(ODBCQueryOptions::new().batch_size(7)))
+    .await
+    .unwrap();
+    assert_eq!(1000, rows.len());
+    for (i, row) in rows.iter().enumerate() {
+        let x: i64 = row.get(0);
+        let text: String = row.get(1);
+        assert_eq!((i as i64 + 1, format!("row {}", i + 1)), (x, text));
+    }
+}
+
+/// Keeps the statements sqlx logs, so tests can check them.
+struct CapturingLogger;
+
+static LOGGED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, _: &log::Metadata<'_>) -> bool {
+        true
+    }
+
+    fn log(&self, record: &log::Record<'_>) {
+        if record.target() == "sqlx::query" {
+            let message = format!("{} {}", record.level(), record.args());
+            LOGGED.lock().unwrap().push(message);
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+fn capture_logs() {
+    static INIT: Once = Once::new();
+    INIT.call_once(|| {
+        log::set_logger(&CapturingLogger).unwrap();
+        log::set_max_level(LevelFilter::Trace);
+    });
+}
+
+/// The statements logged so far which mention `sql`. Tests run concurrently, so each of them
+/// uses statements of its own.
+fn logged(sql: &str) -> Vec<String> {
+    let logged = LOGGED.lock().unwrap();
+    logged.iter().filter(|m| m.contains(sql)).cloned().collect()
+}
+
+#[tokio::test]
+async fn log_statements() {
+    capture_logs();
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .log_statements(LevelFilter::Info)
+            .log_slow_statements(LevelFilter::Warn, Duration::from_secs(3600))
+            .connect()
+            .await
+            .unwrap();
+    conn.execute("CREATE TABLE logged(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO logged(x) VALUES (1), (2), (3)")
+        .await
+        .unwrap();
+    conn.fetch_all("SELECT x FROM logged").await.unwrap();
+
+    let inserted = logged("INSERT INTO logged");
+    assert_eq!(1, inserted.len());
+    assert!(inserted[0].starts_with("INFO"));
+    assert!(inserted[0].contains("rows_affected=3"));
+    let selected = logged("SELECT x FROM logged");
+    assert_eq!(1, selected.len());
+    assert!(selected[0].starts_with("INFO"));
+    assert!(selected[0].contains("rows_returned=3"));
+    assert!(selected[0].contains("elapsed="));
+
+    // Every statement takes at least as long as a zero threshold
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .log_statements(LevelFilter::Info)
+            .log_slow_statements(LevelFilter::Warn, Duration::ZERO)
+            .connect()
+            .await
+            .unwrap();
+    conn.fetch_all("SELECT 42 AS slowly_logged").await.unwrap();
+    let slow = logged("slowly_logged");
+    assert_eq!(1, slow.len());
+    assert!(slow[0].starts_with("WARN"));
+    assert!(slow[0].contains("slow statement"));
+}
+
+/// Counts to a billion, which takes far longer than any of the timeouts.
+const LONG_QUERY: &str = "WITH RECURSIVE c(x) AS \
+     (SELECT 1 UNION ALL SELECT x + 1 FROM c WHERE x < 1000000000) \
+     SELECT count(*) FROM c";
+
+#[tokio::test]
+async fn query_timeout() {
+    let mut conn =
+        ODBCConnectOptions::new("Driver=/opt/homebrew/lib/libsqlite3odbc.dylib;Database=:memory:;")
+            .query_timeout(Duration::from_secs(1))
+            .connect()
+            .await
+            .unwrap();
+    let err = query(LONG_QUERY).fetch_one(&mut conn).await.err().unwrap();
+    assert_eq!(
+        Some("HYT00"),
+        err.as_database_error().unwrap().code().as_deref()
+    );
+    let val: i64 = query("select 42")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(42, val);
+
+    // The timeout of a query overrides the one of the connection
+    let val: i64 = query("select 42")
+        .fetch_one(conn.with_options(ODBCQueryOptions::new().query_timeout(Duration::ZERO)))
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(42, val);
+    let mut conn = test_connection().await;
+    let err = query(LONG_QUERY)
+        .fetch_one(conn.with_options(ODBCQueryOptions::new().query_timeout(Duration::from_secs(1))))
+        .await
+        .err()
+        .unwrap();
+    assert_eq!(
+        Some("HYT00"),
+        err.as_database_error().unwrap().code().as_deref()
+    );
+}
+
+#[tokio::test]
+async fn dropped_stream() {
+    let mut conn = test_connection().await;
+    {
+        let mut rows = query(
+            "with recursive cte(x) as
+                (select 1 union all select x + 1 from cte where x < 100000)
+                select x from cte",
+        )
+        .fetch(&mut conn);
+        rows.next().await.unwrap().unwrap();
+    }
+    let val: i64 = query("select 42")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(42, val);
+}
+
+#[tokio::test]
+async fn fetch_one_of_many() {
+    let mut conn = test_connection().await;
+    conn.execute("CREATE TABLE test(x INTEGER NOT NULL)")
+        .await
+        .unwrap();
+    conn.execute("INSERT INTO test(x) VALUES (1), (2), (3)")
+        .await
+        .unwrap();
+    // The stream is dropped with the cursor still open, on the cached statement as well
+    for _ in 0..3 {
+        let val: i64 = query("SELECT x FROM test ORDER BY x")
+            .fetch_one(&mut conn)
+            .await
+            .unwrap()
+            .get(0);
+        assert_eq!(1, val);
+        let row = conn
+            .fetch_optional("SELECT x FROM test ORDER BY x DESC")
+            .await
+            .unwrap();
+        assert_eq!(3, row.unwrap().get::<i64, _>(0));
+    }
+    let val: i64 = query("SELECT count(*) FROM test")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(3, val);
+}
+
+#[tokio::test]
+async fn cancel_handle() {
+    let mut conn = test_connection().await;
+    let cancel = conn.cancel_handle();
+    // Nothing is running, so there is nothing to cancel
+    cancel.clone().cancel().unwrap();
+
+    // Keeps cancelling, as the statement may not be executing yet the first time
+    let done = Arc::new(AtomicBool::new(false));
+    let canceller = std::thread::spawn({
+        let done = Arc::clone(&done);
+        move || {
+            while !done.load(Ordering::Relaxed) {
+                std::thread::sleep(Duration::from_millis(50));
+                cancel.cancel().unwrap();
+            }
+        }
+    });
+    let res = query(LONG_QUERY).fetch_one(&mut conn).await;
+    done.store(true, Ordering::Relaxed);
+    canceller.join().unwrap();
+    let err = res.err().unwrap();
+    assert_eq!(
+        Some("HY008"),
+        err.as_database_error().unwrap().code().as_deref()
+    );
+
+    // The connection can be used again afterwards
+    let val: i64 = query("select 42")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap()
+        .get(0);
+    assert_eq!(42, val);
+}
+
+#[derive(sqlx::FromRow, Debug, PartialEq)]
+struct Number {
+    num: i64,
+    name: String,
+}
+
+#[tokio::test]
+async fn query_as_from_row() {
+    let mut conn = test_connection().await;
+    let rows: Vec<Number> = sqlx::query_as(
+        "with cte(num, name) as
+            (values (1,'one'), (2, 'two'))
+            select * from cte",
+    )
+    .fetch_all(&mut conn)
+    .await
+    .unwrap();
+    assert_eq!(
+        vec![
+            Number {
+                num: 1,
+                name: "one".to_owned()
+            },
+            Number {
+                num: 2,
+                name: "two".to_owned()
+            }
+        ],
+        rows
+    );
+}
+
+#[tokio::test]
+async fn database_error() {
+    let mut conn = test_connection().await;
+    let err = conn
+        .execute("select * from no_such_table")
+        .await
+        .err()
+        .unwrap();
+    let db_err = err.as_database_error().unwrap();
+    assert_eq!(5, db_err.code().unwrap().len());
+
+    let odbc_err = db_err.downcast_ref::<sqlx_odbc::ODBCDatabaseError>();
+    assert!(!odbc_err.records().is_empty());
+    assert!(odbc_err.records()[0].message().contains("no_such_table"));
+}
+
+#[tokio::test]
+async fn decode_errors() {
+    let mut conn = test_connection().await;
+    let row = query("select 'text' as s, null as n")
+        .fetch_one(&mut conn)
+        .await
+        .unwrap();
+    assert!(matches!(
+        row.try_get::<i32, _>("s"),
+        Err(sqlx::Error::ColumnDecode { .. })
+    ));
+    assert!(matches!(
+        row.try_get::<String, _>("n"),
+        Err(sqlx::Error::ColumnDecode { .. })
+    ));
+    assert_eq!(None, row.try_get::<Option<String>, _>("n").unwrap());
+}